@@ -0,0 +1,252 @@
+use std::{cell::RefCell, collections::HashSet, rc::Rc};
+
+use htmeta::{
+    kdl::KdlNode, kdl_ext::KdlNodeExt, EmitInfo, EmitResult, EmitStatus, IPlugin, Indent,
+    PluginContext, Vars,
+};
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+/// Attributes that give an element an accessible name without any visible text.
+const NAME_ATTRIBUTES: &[&str] = &["aria-label", "aria-labelledby", "title"];
+/// `input` types that don't need a label.
+const UNLABELED_INPUTS: &[&str] = &["hidden", "submit", "button", "reset", "image"];
+
+/// A form field without a label around it, which might be labeled by `id` later on.
+#[derive(Debug)]
+struct Field {
+    id: Option<String>,
+    node: KdlNode,
+}
+
+#[derive(Debug, Default)]
+struct A11yState {
+    /// The tags around the one being emitted, along with their levels.
+    ancestors: Vec<(Indent, String)>,
+    /// The level of the last heading, like `2` for `h2`.
+    last_heading: Option<usize>,
+    /// The `for` of every label so far.
+    labels: HashSet<String>,
+    /// Fields that need a `label for` them, checked once the whole document is emitted.
+    fields: Vec<Field>,
+}
+
+/// Warns about common accessibility mistakes, through [`HtmlEmitter::warn_at`]. Nothing
+/// is added to the output.
+///
+/// The following are reported:
+///  - `html` without a `lang` attribute.
+///  - `img` without an `alt` attribute.
+///  - Headings that skip levels, like an `h4` right after an `h2`.
+///  - `button`s without text or an `aria-label`.
+///  - Form fields that are neither inside a `label`, referenced by one, nor have an
+///    `aria-label`.
+///
+/// To check tags emitted by other plugins, like templates, this plugin must be added
+/// before them.
+///
+/// [`HtmlEmitter::warn_at`]: htmeta::HtmlEmitter::warn_at
+#[derive(Debug, Clone, Default)]
+pub struct A11yPlugin {
+    // Shared between every copy of the plugin, as nested tags are emitted by other emitters.
+    state: Rc<RefCell<A11yState>>,
+}
+
+impl A11yPlugin {
+    fn check(&self, node: &KdlNode, name: &str, context: &PluginContext) {
+        let emitter = context.emitter;
+        let value = |key| {
+            node.get(key)
+                .map(|value| emitter.vars.expand_value(value).into_owned())
+        };
+        let has_name = NAME_ATTRIBUTES.iter().any(|key| node.get(*key).is_some());
+        let mut state = self.state.borrow_mut();
+        match name {
+            "html" if node.get("lang").is_none() => emitter.warn_at(
+                r#"html: Pages need a `lang` attribute, like `lang="en"`."#,
+                node,
+            ),
+            "img" if node.get("alt").is_none() => emitter.warn_at(
+                r#"img: Images need an `alt` attribute. Use `alt=""` for decorative ones."#,
+                node,
+            ),
+            "button" if !has_name && !has_text(node, &emitter.vars) => {
+                emitter.warn_at("button: Buttons need text or an `aria-label`.", node)
+            }
+            "label" => state.labels.extend(value("for")),
+            "input" | "select" | "textarea" => {
+                let input_type = value("type").unwrap_or_default();
+                let in_label = state.ancestors.iter().any(|(_, tag)| tag == "label");
+                if !(has_name || in_label || UNLABELED_INPUTS.contains(&input_type.as_str())) {
+                    state.fields.push(Field {
+                        id: value("id"),
+                        node: node.clone(),
+                    });
+                }
+            }
+            _ => (),
+        }
+
+        if let Some(level) = HEADINGS.iter().position(|heading| *heading == name) {
+            let level = level + 1;
+            if let Some(last) = state.last_heading
+                && level > last + 1
+            {
+                emitter.warn_at(
+                    format!(
+                        "{name}: Heading levels shouldn't be skipped, this one follows an h{last}."
+                    ),
+                    node,
+                );
+            }
+            state.last_heading = Some(level);
+        }
+    }
+}
+
+/// Returns whether `node` has any text inside it, or something that could have text.
+fn has_text(node: &KdlNode, vars: &Vars) -> bool {
+    if let Some(content) = node.inline_text()
+        && !vars.expand_value(content).trim().is_empty()
+    {
+        return true;
+    }
+    node.children()
+        .map(|children| children.nodes())
+        .unwrap_or_default()
+        .iter()
+        .any(|child| match child.name().value() {
+            "img" => child
+                .get("alt")
+                .is_some_and(|alt| !vars.expand_value(alt).is_empty()),
+            // Commands, like templates, might emit text
+            name if name.starts_with('@') => true,
+            _ => has_text(child, vars),
+        })
+}
+
+impl IPlugin for A11yPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        let name = node.name().value();
+        if name.starts_with(['@', '$', '-']) {
+            return Ok(EmitStatus::Skip);
+        }
+        // Nodes are emitted in order, so the ones at this level or deeper are done with
+        let level = context.emitter.current_level;
+        {
+            let mut state = self.state.borrow_mut();
+            while state
+                .ancestors
+                .last()
+                .is_some_and(|(ancestor, _)| *ancestor >= level)
+            {
+                state.ancestors.pop();
+            }
+        }
+        self.check(node, name, &context);
+        self.state
+            .borrow_mut()
+            .ancestors
+            .push((level, name.to_string()));
+        Ok(EmitStatus::Skip)
+    }
+
+    fn on_start(&self, _: &mut Vars, _: &EmitInfo) -> EmitResult {
+        *self.state.borrow_mut() = A11yState::default();
+        Ok(())
+    }
+
+    fn on_end(&self, context: PluginContext) -> EmitResult {
+        let state = self.state.borrow();
+        for field in &state.fields {
+            if field
+                .id
+                .as_ref()
+                .is_none_or(|id| !state.labels.contains(id))
+            {
+                context.emitter.warn_at(
+                    format!(
+                        "{}: Form fields need a `label` or an `aria-label`.",
+                        field.node.name().value()
+                    ),
+                    &field.node,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use htmeta::{kdl::KdlDocument, HtmlEmitter};
+
+    fn warnings(input: &str) -> Vec<String> {
+        let doc: KdlDocument = input.parse().unwrap();
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(A11yPlugin::default());
+        let mut emitter = builder.build();
+        emitter.emit(&doc, &mut Vec::<u8>::new()).unwrap();
+        emitter
+            .take_warnings()
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    #[test]
+    fn accessible_page() {
+        let input = r#"
+            html lang="en" {
+                body {
+                    h1 "Title"
+                    img src="cat.png" alt="A cat"
+                    h2 "Form"
+                    form {
+                        label for="name" "Name"
+                        input id="name"
+                        label {
+                            - "Email"
+                            input type="email"
+                        }
+                        input type="search" aria-label="Search"
+                        input type="hidden" name="token"
+                        button {
+                            img src="send.svg" alt="Send"
+                        }
+                    }
+                }
+            }
+        "#;
+        htmeta_auto_test::assert_eq!(warnings(input), Vec::<String>::new());
+    }
+
+    #[test]
+    fn inaccessible_page() {
+        let input = r#"
+            html {
+                body {
+                    h1 "Title"
+                    h3 "Skipped a level"
+                    img src="cat.png"
+                    input id="name"
+                    textarea ""
+                    button class="icon" {
+                        span class="icon-send"
+                    }
+                }
+            }
+        "#;
+        htmeta_auto_test::assert_eq!(
+            warnings(input),
+            vec![
+                r#"html: Pages need a `lang` attribute, like `lang="en"`."#,
+                "h3: Heading levels shouldn't be skipped, this one follows an h1.",
+                r#"img: Images need an `alt` attribute. Use `alt=""` for decorative ones."#,
+                "button: Buttons need text or an `aria-label`.",
+                "input: Form fields need a `label` or an `aria-label`.",
+                "textarea: Form fields need a `label` or an `aria-label`.",
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Checking that internal links point to files that exist, for `--check-links`.
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use htmeta::{kdl::KdlNode, EmitInfo, EmitResult, EmitStatus, IPlugin, PluginContext, Vars};
+use miette::{LabeledSpan, NamedSource, Report, SourceSpan};
+
+/// Attributes that link to other files.
+const LINK_ATTRIBUTES: &[&str] = &["href", "src", "action"];
+
+#[derive(Debug, Clone)]
+struct Link {
+    url: String,
+    span: SourceSpan,
+}
+
+/// Collects the internal links of every tag as it is emitted, so they can be checked once
+/// the page is written.
+#[derive(Debug, Clone, Default)]
+pub struct LinkCollector {
+    // Shared between every copy of the plugin, as nested tags are emitted by other emitters.
+    links: Rc<RefCell<Vec<Link>>>,
+}
+
+impl LinkCollector {
+    /// Returns a report for each collected link that doesn't point to an existing file.
+    /// Links are resolved like [`resolve`] does, with `root` and `dir`. `filename` and
+    /// `contents` are the document's, for labeling where the links are.
+    pub fn check(&self, root: &Path, dir: &Path, filename: &Path, contents: &str) -> Vec<Report> {
+        self.links
+            .borrow()
+            .iter()
+            .filter(|link| !exists(&resolve(root, dir, &link.url)))
+            .map(|link| {
+                // Links from `_defaults.kdl` files have spans past the end of the page
+                let labels: Vec<_> = Some(link.span)
+                    .filter(|span| span.offset() + span.len() <= contents.len())
+                    .map(|span| LabeledSpan::at(span, "this link"))
+                    .into_iter()
+                    .collect();
+                miette::miette!(
+                    labels = labels,
+                    "Broken link: `{}` doesn't exist.",
+                    link.url
+                )
+                .with_source_code(NamedSource::new(
+                    filename.to_string_lossy(),
+                    contents.to_string(),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Returns whether `url` links to another file of the same site.
+pub(crate) fn is_internal(url: &str) -> bool {
+    let has_scheme = url
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.contains('/'));
+    !(url.is_empty() || has_scheme || url.starts_with("//") || url.starts_with('#'))
+}
+
+/// Returns the path of the file the internal link `url` points to. Links starting with `/`
+/// are relative to `root`, the site's root, and others to `dir`, the page's directory.
+pub(crate) fn resolve(root: &Path, dir: &Path, url: &str) -> PathBuf {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path.strip_prefix('/') {
+        Some(path) => root.join(path),
+        None => dir.join(path),
+    }
+}
+
+/// Returns whether the file at `path` exists. Links to directories need an `index.html`
+/// inside them, and links without an extension may leave out `.html`.
+fn exists(path: &Path) -> bool {
+    path.is_file() || path.join("index.html").is_file() || path.with_extension("html").is_file()
+}
+
+impl IPlugin for LinkCollector {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        let name = node.name().value();
+        if name.starts_with(['@', '$', '-']) {
+            return Ok(EmitStatus::Skip);
+        }
+        for entry in node.entries() {
+            if let Some(key) = entry.name()
+                && LINK_ATTRIBUTES.contains(&key.value())
+            {
+                let url = context.emitter.vars.expand_value(entry.value());
+                // Links that depend on unset variables can't be checked
+                if is_internal(&url) && !url.contains('$') {
+                    self.links.borrow_mut().push(Link {
+                        url: url.into_owned(),
+                        span: entry.span(),
+                    });
+                }
+            }
+        }
+        Ok(EmitStatus::Skip)
+    }
+
+    fn on_start(&self, _: &mut Vars, _: &EmitInfo) -> EmitResult {
+        self.links.borrow_mut().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_links() {
+        let (root, dir) = (Path::new("site"), Path::new("site/blog"));
+        htmeta_auto_test::assert_eq!(
+            resolve(root, dir, "/style.css?v=2"),
+            PathBuf::from("site/style.css")
+        );
+        htmeta_auto_test::assert_eq!(
+            resolve(root, dir, "post.html#intro"),
+            PathBuf::from("site/blog/post.html")
+        );
+        htmeta_auto_test::assert_eq!(
+            resolve(root, dir, "../about"),
+            PathBuf::from("site/blog/../about")
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Running the `*.test.kdl` files of a component library, for `htmeta test`.
+//!
+//! Each `card.test.kdl` file has `test "name" { ... }` nodes, whose children use the
+//! templates declared in `card.kdl`, next to it, along with an `expect` node holding the
+//! `HTML` they should emit:
+//!
+//! ```kdl
+//! test "renders the title" {
+//!     @card title="Hi"
+//!     expect """
+//!         <div class="card">
+//!             <h2>Hi</h2>
+//!         </div>
+//!         """
+//! }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use htmeta::{kdl::KdlDocument, HtmlEmitterBuilder};
+use miette::{Context, IntoDiagnostic};
+
+const SUFFIX: &str = ".test.kdl";
+
+#[derive(Debug)]
+struct Failure {
+    name: String,
+    expected: String,
+    found: String,
+}
+
+/// Returns the `*.test.kdl` files at `path`, which can be one of them or a directory to
+/// search recursively.
+fn test_files(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.into()]);
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(test_files(&path)?);
+        } else if path.to_string_lossy().ends_with(SUFFIX) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_document(path: &Path) -> miette::Result<KdlDocument> {
+    let contents = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .with_context(|| format!("Could not open file {}.", path.display()))?;
+    Ok(htmeta::parse_document(&contents)?)
+}
+
+/// Returns `html` without blank lines around it nor trailing whitespace, so it can be
+/// compared regardless of how the expectation was written.
+fn normalize(html: &str) -> String {
+    let lines: Vec<_> = html.trim().lines().map(str::trim_end).collect();
+    lines.join("\n")
+}
+
+/// Runs the tests of the file at `path` and returns how many there were, along with the
+/// ones that failed.
+fn run_file(builder: &HtmlEmitterBuilder, path: &Path) -> miette::Result<(usize, Vec<Failure>)> {
+    let file_name = path.to_string_lossy();
+    let components = file_name.strip_suffix(SUFFIX).unwrap_or(&file_name);
+    let components = PathBuf::from(format!("{components}.kdl"));
+    let mut components = match components.exists() {
+        true => read_document(&components)?,
+        false => KdlDocument::new(),
+    };
+    // Like the pages next to them, tests get the `_defaults.kdl` files' nodes
+    crate::defaults::apply(&mut components, path)?;
+
+    let tests = read_document(path)?;
+    let mut failures = Vec::new();
+    let tests: Vec<_> = tests
+        .nodes()
+        .iter()
+        .filter(|node| node.name().value() == "test")
+        .collect();
+    for test in &tests {
+        let name = match test.get(0).and_then(|name| name.as_string()) {
+            Some(name) => format!("{file_name}: {name}"),
+            None => file_name.to_string(),
+        };
+        let mut document = components.clone();
+        let mut expected = None;
+        for node in test.children().map(KdlDocument::nodes).unwrap_or_default() {
+            match node.name().value() {
+                "expect" => expected = node.get(0).and_then(|html| html.as_string()),
+                _ => document.nodes_mut().push(node.clone()),
+            }
+        }
+        let expected = expected.ok_or_else(|| miette::miette!("{name}: Missing `expect`."))?;
+
+        let mut found = Vec::new();
+        let mut emitter = builder.build();
+        emitter.reset_for(path);
+        let found = match emitter.emit(&document, &mut found) {
+            Ok(()) => String::from_utf8_lossy(&found).into_owned(),
+            Err(err) => format!("Error: {err}"),
+        };
+        if normalize(&found) != normalize(expected) {
+            failures.push(Failure {
+                name,
+                expected: normalize(expected),
+                found: normalize(&found),
+            });
+        }
+    }
+    Ok((tests.len(), failures))
+}
+
+/// Runs every test at `path`, printing their results, and fails if any of them did.
+pub fn run(builder: &HtmlEmitterBuilder, path: &Path) -> miette::Result<()> {
+    let files = test_files(path)
+        .into_diagnostic()
+        .with_context(|| format!("Could not read {}.", path.display()))?;
+    let mut count = 0;
+    let mut failures = Vec::new();
+    for file in files {
+        let (tests, failed) = run_file(builder, &file)?;
+        count += tests;
+        failures.extend(failed);
+    }
+
+    let indent = |html: &str| html.replace('\n', "\n    ");
+    for failure in &failures {
+        eprintln!("FAILED {}", failure.name);
+        eprintln!("  expected:\n    {}", indent(&failure.expected));
+        eprintln!("  found:\n    {}\n", indent(&failure.found));
+    }
+    println!(
+        "{} passed, {} failed.",
+        count - failures.len(),
+        failures.len()
+    );
+    if !failures.is_empty() {
+        return Err(miette::miette!(
+            "{} of {count} tests failed.",
+            failures.len()
+        ));
+    }
+    Ok(())
+}
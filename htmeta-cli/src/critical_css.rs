@@ -0,0 +1,175 @@
+//! Inlining the CSS a page uses, for `--critical-css`.
+
+use std::{path::Path, sync::LazyLock};
+
+use htmeta::{
+    css::{self, Element, Selector, ATTRIBUTE, TAG},
+    EmitResult, PostProcessor,
+};
+use regex::Regex;
+
+/// Parts of selectors that don't depend on which elements exist: attribute selectors,
+/// pseudo-classes and pseudo-elements.
+static IGNORED: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[[^\]]*\]|::?[\w-]+(\([^)]*\))?").unwrap());
+/// Combinators between compound selectors, like `>` in `main > p`.
+static COMBINATOR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s*[\s>+~]\s*").unwrap());
+
+/// Inlines the rules of a stylesheet that match the page's elements into a `<style>` in its
+/// `head`, and loads links to the whole stylesheet without blocking rendering.
+///
+/// Rules are kept if each compound selector in them, like `p.note` in `main > p.note`,
+/// matches an element of the page, so some unused rules may be kept. At-rules, like `@media`, are always left to the stylesheet.
+pub struct CriticalCss {
+    css: String,
+    file_name: String,
+}
+
+impl CriticalCss {
+    /// Reads the stylesheet at `path`. Links to it are recognized by its file name.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            css: std::fs::read_to_string(path)?,
+            file_name: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into(),
+        })
+    }
+
+    /// Returns the rules that can apply to `elements`.
+    fn critical_rules(&self, elements: &[Element]) -> String {
+        let css = css::strip_comments(&self.css);
+        let mut critical = String::new();
+        for rule in css::rules(&css) {
+            if rule.body.is_none() || rule.is_at_rule() {
+                continue;
+            }
+            if rule
+                .prelude
+                .split(',')
+                .any(|selector| is_used(selector, elements))
+            {
+                critical.push_str(&rule.to_string());
+            }
+        }
+        critical
+    }
+
+    /// Returns `tag` changed to load the stylesheet without blocking rendering, if it is a
+    /// link to it.
+    fn defer(&self, tag: &str, attributes: &str) -> Option<String> {
+        let mut is_stylesheet = false;
+        let mut href = None;
+        for captures in ATTRIBUTE.captures_iter(attributes) {
+            match &captures[1] {
+                "rel" => is_stylesheet = &captures[2] == "stylesheet",
+                "href" => href = Some(captures[2].to_string()),
+                _ => (),
+            }
+        }
+        let href = href.filter(|href| is_stylesheet && href.ends_with(&self.file_name))?;
+        Some(format!(
+            concat!(
+                r#"<link rel="preload" href="{href}" as="style" "#,
+                r#"onload="this.onload=null;this.rel='stylesheet'"><noscript>{tag}</noscript>"#
+            ),
+            href = href,
+            tag = tag
+        ))
+    }
+}
+
+/// Returns whether every compound selector of `selector` matches one of `elements`. Those
+/// that can't be parsed are assumed to match.
+fn is_used(selector: &str, elements: &[Element]) -> bool {
+    let selector = IGNORED.replace_all(selector, "");
+    COMBINATOR
+        .split(selector.trim())
+        .filter(|compound| !compound.is_empty())
+        .all(|compound| match Selector::parse(compound) {
+            Some(compound) => elements.iter().any(|element| compound.matches(element)),
+            None => true,
+        })
+}
+
+impl PostProcessor for CriticalCss {
+    fn process(&self, html: String) -> EmitResult<String> {
+        let Some(head_end) = html.find("</head>") else {
+            return Ok(html);
+        };
+        let critical = self.critical_rules(&css::elements(&html));
+        let (head, body) = html.split_at(head_end);
+        let head = TAG.replace_all(head, |tag: &regex::Captures| {
+            match &tag[1] {
+                "link" => self.defer(&tag[0], &tag[2]),
+                _ => None,
+            }
+            .unwrap_or_else(|| tag[0].to_string())
+        });
+        match critical.is_empty() {
+            true => Ok(format!("{head}{body}")),
+            false => Ok(format!("{head}<style>{critical}</style>{body}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn critical(css: &str, html: &str) -> String {
+        let critical_css = CriticalCss {
+            css: css.into(),
+            file_name: "style.css".into(),
+        };
+        critical_css.critical_rules(&css::elements(html))
+    }
+
+    const HTML: &str = r#"<main id="Top"><P class="Intro note">Hi</P></main>"#;
+
+    #[test]
+    fn matching_rules() {
+        htmeta_auto_test::assert_eq!(
+            critical(
+                "p { color: red } #Top { margin: 0 } .note::after { content: '!' }",
+                HTML
+            ),
+            "p{color: red}#Top{margin: 0}.note::after{content: '!'}"
+        );
+    }
+
+    #[test]
+    fn non_matching_rules() {
+        // Classes and `id`s are case-sensitive
+        htmeta_auto_test::assert_eq!(
+            critical(
+                "h1 { color: red } .intro { margin: 0 } #top { margin: 0 }",
+                HTML
+            ),
+            ""
+        );
+    }
+
+    #[test]
+    fn compound_selectors() {
+        htmeta_auto_test::assert_eq!(
+            critical(
+                "main > p.Intro { color: red } p.Intro.missing { color: blue } h1, .note { margin: 0 }",
+                HTML
+            ),
+            "main > p.Intro{color: red}h1, .note{margin: 0}"
+        );
+    }
+
+    #[test]
+    fn no_critical_rules() {
+        let critical_css = CriticalCss {
+            css: "h1 { color: red }".into(),
+            file_name: "style.css".into(),
+        };
+        let html = "<html><head></head><body><p>Hi</p></body></html>".to_string();
+        htmeta_auto_test::assert_eq!(critical_css.process(html.clone()).unwrap(), html);
+    }
+}
@@ -0,0 +1,52 @@
+//! `Content-Security-Policy` nonces, for `--csp`.
+
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+
+/// Where the policy allowing the page's nonce goes.
+#[derive(Debug, Clone, Copy)]
+pub enum CspOutput {
+    /// A `meta` tag at the start of the page's `head`.
+    Meta,
+    /// A `.headers` file next to the page, for the web server to send.
+    Header,
+}
+
+/// Returns a new nonce made of 16 random bytes, encoded in base64.
+pub fn random_nonce() -> String {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("Could not get random bytes for a CSP nonce");
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+/// Returns the path of the headers file of the page at `page`, like `index.html.headers`.
+fn headers_path(page: &Path) -> PathBuf {
+    let mut path = page.as_os_str().to_owned();
+    path.push(".headers");
+    path.into()
+}
+
+/// Writes `policy` as a `Content-Security-Policy` header into the headers file of `page`.
+pub fn write_header(page: &Path, policy: &str) -> std::io::Result<()> {
+    std::fs::write(
+        headers_path(page),
+        format!("Content-Security-Policy: {policy}\n"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonces_differ() {
+        let nonce = random_nonce();
+        htmeta_auto_test::assert_eq!(nonce.len(), 24);
+        assert_ne!(nonce, random_nonce());
+        htmeta_auto_test::assert_eq!(
+            headers_path(Path::new("out/index.html")),
+            PathBuf::from("out/index.html.headers")
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! `_defaults.kdl` files, whose nodes are added to the start of every page in their
+//! directory and below it.
+//!
+//! Those nodes aren't part of the page's source, so their spans are moved past its end,
+//! where reports don't label them.
+
+use std::path::{Path, PathBuf};
+
+use htmeta::kdl::{KdlDocument, KdlNode};
+use miette::{Context, IntoDiagnostic};
+
+const DEFAULTS: &str = "_defaults.kdl";
+
+/// The span given to nodes from `_defaults.kdl` files, which is past the end of any page.
+const FOREIGN_SPAN: std::ops::Range<usize> = usize::MAX..usize::MAX;
+
+/// Returns the `_defaults.kdl` files that apply to the page at `input`, outermost first:
+/// the one next to it, and those in each parent directory up to `cwd`.
+fn defaults_files(input: &Path, cwd: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let dir = match input.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let dir = dir.canonicalize()?;
+    let cwd = cwd.canonicalize()?;
+    let mut files: Vec<_> = match dir.starts_with(&cwd) {
+        true => dir
+            .ancestors()
+            .take_while(|ancestor| ancestor.starts_with(&cwd))
+            .map(|ancestor| ancestor.join(DEFAULTS))
+            .collect(),
+        // Pages outside of the working directory only get their own directory's defaults
+        false => vec![dir.join(DEFAULTS)],
+    };
+    files.retain(|file| file.is_file());
+    files.reverse();
+    Ok(files)
+}
+
+/// Adds the nodes of the `_defaults.kdl` files that apply to the page at `input` to the
+/// start of `doc`, so deeper directories and the page itself can override them.
+pub fn apply(doc: &mut KdlDocument, input: &Path) -> miette::Result<()> {
+    let files = std::env::current_dir()
+        .and_then(|cwd| defaults_files(input, &cwd))
+        .into_diagnostic()
+        .context("Could not look for `_defaults.kdl` files.")?;
+    doc.nodes_mut().splice(0..0, load(&files)?);
+    Ok(())
+}
+
+/// Returns the nodes of every file in `files`, in order, with their spans moved to
+/// [`FOREIGN_SPAN`].
+fn load(files: &[PathBuf]) -> miette::Result<Vec<KdlNode>> {
+    let mut nodes = Vec::new();
+    for file in files {
+        let contents = std::fs::read_to_string(file)
+            .into_diagnostic()
+            .with_context(|| format!("Could not open file {}.", file.display()))?;
+        let defaults = htmeta::parse_document(&contents)
+            .into_diagnostic()
+            .with_context(|| format!("Could not parse {}.", file.display()))?;
+        nodes.extend(defaults.nodes().iter().cloned().map(|mut node| {
+            detach(&mut node);
+            node
+        }));
+    }
+    Ok(nodes)
+}
+
+/// Moves the spans of `node`, its entries and its children to [`FOREIGN_SPAN`].
+fn detach(node: &mut KdlNode) {
+    node.set_span(FOREIGN_SPAN);
+    node.name_mut().set_span(FOREIGN_SPAN);
+    for entry in node.entries_mut() {
+        entry.set_span(FOREIGN_SPAN);
+    }
+    if let Some(children) = node.children_mut() {
+        children.set_span(FOREIGN_SPAN);
+        children.nodes_mut().iter_mut().for_each(detach);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("htmeta-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("blog/2024")).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn lookup_order() {
+        let root = temp_dir("defaults-lookup");
+        std::fs::write(root.join(DEFAULTS), "").unwrap();
+        std::fs::write(root.join("blog/2024").join(DEFAULTS), "").unwrap();
+        let page = root.join("blog/2024/post.kdl");
+        htmeta_auto_test::assert_eq!(
+            defaults_files(&page, &root).unwrap(),
+            vec![root.join(DEFAULTS), root.join("blog/2024").join(DEFAULTS)]
+        );
+        // Files above the working directory are ignored
+        htmeta_auto_test::assert_eq!(
+            defaults_files(&page, &root.join("blog")).unwrap(),
+            vec![root.join("blog/2024").join(DEFAULTS)]
+        );
+        // Pages outside of it only get their own directory's
+        htmeta_auto_test::assert_eq!(
+            defaults_files(&root.join("post.kdl"), &root.join("blog")).unwrap(),
+            vec![root.join(DEFAULTS)]
+        );
+    }
+
+    #[test]
+    fn merging() {
+        let root = temp_dir("defaults-merging");
+        std::fs::write(root.join(DEFAULTS), "$title \"Site\"\n$lang en").unwrap();
+        std::fs::write(root.join("blog").join(DEFAULTS), "$title \"Blog\"").unwrap();
+        let files = defaults_files(&root.join("blog/post.kdl"), &root).unwrap();
+        let mut doc = htmeta::parse_document("p \"$title ($lang)\"").unwrap();
+        doc.nodes_mut().splice(0..0, load(&files).unwrap());
+        let mut buf = Vec::new();
+        htmeta::HtmlEmitter::builder()
+            .build()
+            .emit(&doc, &mut buf)
+            .unwrap();
+        htmeta_auto_test::assert_eq!(String::from_utf8(buf).unwrap(), "<p>Blog (en)</p>\n");
+        // Only the page's own nodes keep their spans
+        for node in &doc.nodes()[..3] {
+            htmeta_auto_test::assert_eq!(node.span().offset(), usize::MAX);
+        }
+        htmeta_auto_test::assert_eq!(doc.nodes()[3].span().offset(), 0);
+    }
+}
@@ -0,0 +1,23 @@
+use htmeta::{kdl::KdlNode, EmitInfo, EmitResult, EmitStatus, IPlugin, PluginContext, Vars};
+
+/// Defines `$FILENAME` and `$DIRNAME` from the input file's path, like `index.kdl` and
+/// `pages/blog`.
+#[derive(Debug, Clone)]
+pub struct FileVarsPlugin;
+
+impl IPlugin for FileVarsPlugin {
+    fn emit_node(&self, _: &KdlNode, _: PluginContext) -> EmitResult<EmitStatus> {
+        Ok(EmitStatus::Skip)
+    }
+
+    fn on_start(&self, vars: &mut Vars, info: &EmitInfo) -> EmitResult {
+        let Some(filename) = info.filename else {
+            return Ok(());
+        };
+        let name = filename.file_name().unwrap_or_default();
+        let dir = filename.parent().unwrap_or(filename);
+        vars.insert("FILENAME", name.to_string_lossy().into_owned().into());
+        vars.insert("DIRNAME", dir.to_string_lossy().into_owned().into());
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! Comparing emitted `HTML` against an existing file, for `htmeta diff`.
+//!
+//! Pages are compared token by token, as tags and text, so differences in whitespace and
+//! in the order of attributes don't count.
+
+use std::{path::Path, sync::LazyLock};
+
+use htmeta::{kdl::KdlDocument, HtmlEmitterBuilder};
+use miette::{Context, IntoDiagnostic};
+use regex::Regex;
+use similar::{Algorithm, DiffTag};
+
+static TOKEN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<!--.*?-->|<[^>]*>|[^<]+").unwrap());
+static OPENING_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)^<([a-zA-Z][\w-]*)(.*?)/?>$").unwrap());
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"([^\s"'=/>]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+)))?"#).unwrap()
+});
+
+/// How many unchanged tokens are shown around each change.
+const CONTEXT: usize = 2;
+
+/// Returns `tag` with its attributes sorted and quoted the same way.
+fn normalize_tag(tag: &str) -> String {
+    let Some(captures) = OPENING_TAG.captures(tag) else {
+        return tag.split_whitespace().collect::<Vec<_>>().join(" ");
+    };
+    let mut attributes: Vec<_> = ATTRIBUTE
+        .captures_iter(&captures[2])
+        .map(|attribute| {
+            let value = (2..=4).find_map(|i| attribute.get(i));
+            match value {
+                Some(value) => format!(r#"{}="{}""#, &attribute[1], value.as_str()),
+                None => attribute[1].to_string(),
+            }
+        })
+        .collect();
+    attributes.sort();
+    let mut normalized = format!("<{}", captures[1].to_lowercase());
+    for attribute in attributes {
+        normalized.push(' ');
+        normalized.push_str(&attribute);
+    }
+    normalized.push('>');
+    normalized
+}
+
+/// Splits `html` into its tags and text, normalized so insignificant differences go away.
+fn tokens(html: &str) -> Vec<String> {
+    TOKEN
+        .find_iter(html)
+        .filter_map(|token| {
+            let token = token.as_str();
+            if token.starts_with('<') {
+                return Some(normalize_tag(token));
+            }
+            let text = token.split_whitespace().collect::<Vec<_>>().join(" ");
+            (!text.is_empty()).then_some(text)
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Returns the changes that turn `old` into `new`.
+fn changes<'a>(old: &'a [String], new: &'a [String]) -> Vec<Change<'a>> {
+    let mut changes = Vec::new();
+    for op in similar::capture_diff_slices(Algorithm::Myers, old, new) {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        let (removed, added) = (&old[old_range], &new[new_range]);
+        if tag == DiffTag::Equal {
+            changes.extend(removed.iter().map(|token| Change::Same(token)));
+            continue;
+        }
+        changes.extend(removed.iter().map(|token| Change::Removed(token)));
+        changes.extend(added.iter().map(|token| Change::Added(token)));
+    }
+    changes
+}
+
+/// Returns a readable diff of `changes`, with a few unchanged tokens around each change.
+fn format_changes(changes: &[Change]) -> String {
+    let is_near_change = |i: usize| {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(changes.len());
+        changes[start..end]
+            .iter()
+            .any(|change| !matches!(change, Change::Same(_)))
+    };
+    let mut diff = String::new();
+    let mut skipped = false;
+    for (i, change) in changes.iter().enumerate() {
+        if !is_near_change(i) {
+            skipped = true;
+            continue;
+        }
+        if std::mem::take(&mut skipped) {
+            diff.push_str("  ...\n");
+        }
+        let line = match change {
+            Change::Same(token) => format!("  {token}\n"),
+            Change::Removed(token) => format!("- {token}\n"),
+            Change::Added(token) => format!("+ {token}\n"),
+        };
+        diff.push_str(&line);
+    }
+    diff
+}
+
+/// Emits `doc`, read from `input`, and compares it against the `HTML` file at `existing`,
+/// printing the differences. Returns whether they are the same.
+fn compare(
+    builder: &HtmlEmitterBuilder,
+    input: &Path,
+    doc: &KdlDocument,
+    existing: &Path,
+) -> miette::Result<bool> {
+    let mut emitter = builder.build();
+    emitter.reset_for(input);
+    let mut emitted = Vec::new();
+    emitter.emit(doc, &mut emitted).into_diagnostic()?;
+    let emitted = String::from_utf8_lossy(&emitted);
+    let existing_html = std::fs::read_to_string(existing)
+        .into_diagnostic()
+        .with_context(|| format!("Could not open file {}.", existing.display()))?;
+
+    let (old, new) = (tokens(&existing_html), tokens(&emitted));
+    let changes = changes(&old, &new);
+    if changes
+        .iter()
+        .all(|change| matches!(change, Change::Same(_)))
+    {
+        return Ok(true);
+    }
+    println!("--- {}", existing.display());
+    println!("+++ {}", input.display());
+    print!("{}", format_changes(&changes));
+    Ok(false)
+}
+
+/// Emits the document at `input` and compares it against the `HTML` file at `existing`,
+/// printing the differences and failing if there are any.
+///
+/// Like when building, each `@document` of the file is compared against the file at its
+/// path, next to `existing`.
+pub fn run(builder: &HtmlEmitterBuilder, input: &Path, existing: &Path) -> miette::Result<()> {
+    let contents = std::fs::read_to_string(input)
+        .into_diagnostic()
+        .with_context(|| format!("Could not open file {}.", input.display()))?;
+    let mut doc = htmeta::parse_document(&contents)?;
+    crate::defaults::apply(&mut doc, input)?;
+
+    let documents = htmeta::split_documents(&doc).into_diagnostic()?;
+    if documents.is_empty() {
+        if compare(builder, input, &doc, existing)? {
+            return Ok(());
+        }
+        return Err(miette::miette!(
+            "{} differs from what {} emits.",
+            existing.display(),
+            input.display()
+        ));
+    }
+    let root = existing.parent().unwrap_or(Path::new(""));
+    let mut differing = 0;
+    for (path, document) in &documents {
+        if !compare(builder, input, document, &root.join(path))? {
+            differing += 1;
+        }
+    }
+    if differing == 0 {
+        return Ok(());
+    }
+    Err(miette::miette!(
+        "{differing} of the {} documents differ from what {} emits.",
+        documents.len(),
+        input.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insignificant_differences() {
+        htmeta_auto_test::assert_eq!(
+            tokens("<p class='a' id=b>\n    Some   text\n</p>"),
+            tokens(r#"<p id="b" class="a">Some text</p>"#)
+        );
+    }
+
+    #[test]
+    fn changed_text() {
+        let old = tokens("<ul><li>One</li><li>Two</li></ul>");
+        let new = tokens("<ul><li>One</li><li>2</li></ul>");
+        htmeta_auto_test::assert_eq!(
+            format_changes(&changes(&old, &new)),
+            "  ...\n  </li>\n  <li>\n- Two\n+ 2\n  </li>\n  </ul>\n"
+        );
+    }
+}
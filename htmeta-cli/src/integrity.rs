@@ -0,0 +1,119 @@
+//! Subresource integrity hashes of local scripts and stylesheets, for `--integrity`.
+
+use std::path::PathBuf;
+
+use base64::Engine;
+use htmeta::{
+    kdl::{KdlEntry, KdlNode},
+    kdl_ext::KdlNodeExt,
+    EmitResult, EmitStatus, FileProvider, IPlugin, PluginContext,
+};
+use sha2::{Digest, Sha384};
+
+use crate::check_links;
+
+/// Adds `integrity` and `crossorigin` attributes to `script`s and stylesheet `link`s that
+/// point to local files, so browsers refuse them if they are changed.
+///
+/// Files are found like [`check_links::resolve`] does, with links starting with `/` read
+/// from `root`, the site's root, and others from `dir`, where the page is written. Tags
+/// that already have an `integrity` attribute are left alone.
+#[derive(Debug, Clone)]
+pub struct IntegrityPlugin {
+    root: PathBuf,
+    dir: PathBuf,
+}
+
+impl IntegrityPlugin {
+    pub fn new(root: impl Into<PathBuf>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            dir: dir.into(),
+        }
+    }
+
+    /// Returns the `integrity` value of the file at `url`, read from `files`.
+    fn integrity(&self, url: &str, files: &dyn FileProvider) -> std::io::Result<String> {
+        let contents = files.read(&check_links::resolve(&self.root, &self.dir, url))?;
+        let hash = base64::engine::general_purpose::STANDARD.encode(sha384(&contents));
+        Ok(format!("sha384-{hash}"))
+    }
+}
+
+/// Returns a new `key=value` property, formatted to be emitted as an attribute.
+fn prop(key: &str, value: String) -> KdlEntry {
+    let mut entry = KdlEntry::new_prop(key, value);
+    entry.autoformat();
+    entry
+}
+
+impl IPlugin for IntegrityPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        let name = node.name().value();
+        let vars = &context.emitter.vars;
+        let value = |key| node.get(key).map(|value| vars.expand_value(value));
+        let url = match name {
+            "script" => value("src"),
+            "link" if value("rel").is_some_and(|rel| rel == "stylesheet") => value("href"),
+            _ => None,
+        };
+        let Some(url) = url.filter(|url| check_links::is_internal(url)) else {
+            return Ok(EmitStatus::Skip);
+        };
+        if node.get("integrity").is_some() {
+            return Ok(EmitStatus::Skip);
+        }
+        let integrity = match self.integrity(&url, context.emitter.files()) {
+            Ok(integrity) => integrity,
+            Err(err) => {
+                let message = format!("{name}: Could not hash `{url}` for its integrity: {err}");
+                context.emitter.warn_at(message, node);
+                return Ok(EmitStatus::Skip);
+            }
+        };
+
+        let mut tag = node.clone();
+        let has_text = tag.inline_text().is_some();
+        // Attributes go before the inline text, if any
+        let at = tag.entries().len() - usize::from(has_text);
+        tag.entries_mut().insert(at, prop("integrity", integrity));
+        if tag.get("crossorigin").is_none() {
+            tag.entries_mut()
+                .insert(at + 1, prop("crossorigin", "anonymous".into()));
+        }
+        context
+            .emitter
+            .emit_tag(&tag, name, context.indent, context.writer)?;
+        Ok(EmitStatus::Emmited)
+    }
+}
+
+/// Returns the SHA-384 hash of `data`, the one recommended for integrity attributes.
+fn sha384(data: &[u8]) -> [u8; 48] {
+    Sha384::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha384_hashes() {
+        let hex = |hash: [u8; 48]| hash.map(|byte| format!("{byte:02x}")).concat();
+        htmeta_auto_test::assert_eq!(
+            hex(sha384(b"abc")),
+            concat!(
+                "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed",
+                "8086072ba1e7cc2358baeca134c825a7"
+            )
+        );
+        // Spans two blocks once padded
+        htmeta_auto_test::assert_eq!(
+            hex(sha384(&[b'a'; 200])),
+            concat!(
+                "0691b6e978614b67d60557b2a2cddd53406508522efa21c624dbbfa8ab6e726d",
+                "5c586b489c7c09f24109a64c10211d48"
+            )
+        );
+    }
+}
@@ -1,7 +1,20 @@
-use htmeta::{kdl, HtmlEmitter, HtmlEmitterBuilder};
-use kdl::KdlDocument;
+use htmeta::{
+    kdl::KdlDocument, DuplicateAttributes, HtmlEmitter, HtmlEmitterBuilder, InvalidAttributes,
+    LinkNormalization, MissingVarAction, OutputMode, TextWhitespace, TrailingSlash,
+};
 use lexopt::Parser;
-use miette::{Context, Diagnostic, IntoDiagnostic};
+use miette::{Context, Diagnostic, IntoDiagnostic, LabeledSpan, NamedSource, Severity};
+mod check_links;
+mod component_tests;
+mod critical_css;
+mod csp;
+mod defaults;
+mod file_vars;
+mod html_diff;
+mod integrity;
+mod profile;
+mod redirects;
+
 use std::{
     ffi::OsString,
     io::{BufWriter, Read, Write},
@@ -36,6 +49,12 @@ struct Args {
     builder: HtmlEmitterBuilder,
     input_filename: PathBuf,
     output_filename: Option<PathBuf>,
+    minified_filename: Option<PathBuf>,
+    link_collector: Option<check_links::LinkCollector>,
+    redirects: redirects::RedirectCollector,
+    redirect_format: redirects::RedirectFormat,
+    csp_header: bool,
+    site_root: PathBuf,
 }
 
 impl Args {
@@ -44,15 +63,140 @@ impl Args {
 
         let mut parser = Parser::from_args(args);
         let mut builder = HtmlEmitter::builder();
-        #[cfg(feature = "templates")]
-        builder.add_plugin(htmeta_template::TemplatePlugin::default());
+        builder.add_plugin(file_vars::FileVarsPlugin);
         let mut input_filename = None;
         let mut output_filename = None;
+        let mut minified_filename = None;
+        let mut site_root = None;
+        let mut link_collector = None;
+        let mut integrity = false;
+        let mut redirect_format = redirects::RedirectFormat::default();
+        let mut csp = false;
+        let mut csp_header = false;
+        let mut critical_css = false;
+        let mut links = LinkNormalization::default();
+        #[cfg(feature = "icons")]
+        let mut icons_dir = None;
+        #[cfg(feature = "toc")]
+        let mut toc = htmeta_toc::TocPlugin::default();
         while let Some(arg) = parser.next()? {
             match arg {
                 Long("minify") | Short('m') => drop(builder.minify()),
                 Long("tab-size") | Short('t') => drop(builder.indent(parser.value()?.parse()?)),
                 Long("document-formatting") | Short('D') => drop(builder.follow_original_indent()),
+                Long("define") | Short('d') => drop(builder.define(&parser.value()?.string()?)),
+                Long("preserve-entities") | Short('e') => drop(builder.preserve_entities()),
+                Long("env-vars") => drop(builder.env_vars()),
+                Long("warn-unused") => drop(builder.warn_unused()),
+                Long("sort-attributes") => drop(builder.sort_attributes()),
+                Long("duplicate-attributes") => {
+                    let policy = match parser.value()?.string()?.as_str() {
+                        "error" => DuplicateAttributes::Error,
+                        "first" => DuplicateAttributes::KeepFirst,
+                        "last" => DuplicateAttributes::KeepLast,
+                        "merge" => DuplicateAttributes::Merge,
+                        other => {
+                            return Err(
+                                format!("Invalid duplicate attribute policy: {other}").into()
+                            )
+                        }
+                    };
+                    builder.duplicate_attributes(policy);
+                }
+                Long("invalid-attributes") => {
+                    let policy = match parser.value()?.string()?.as_str() {
+                        "error" => InvalidAttributes::Error,
+                        "skip" => InvalidAttributes::Skip,
+                        "allow" => InvalidAttributes::Allow,
+                        other => {
+                            return Err(format!("Invalid attribute name policy: {other}").into())
+                        }
+                    };
+                    builder.invalid_attributes(policy);
+                }
+                Long("missing-vars") => {
+                    let action = match parser.value()?.string()?.as_str() {
+                        "empty" => MissingVarAction::EmptyString,
+                        "keep" => MissingVarAction::KeepLiteral,
+                        "error" => MissingVarAction::Error,
+                        other => {
+                            return Err(format!("Invalid missing variable action: {other}").into())
+                        }
+                    };
+                    builder.on_missing_var(move |_| action.clone());
+                }
+                Long("redirects") => {
+                    redirect_format = match parser.value()?.string()?.as_str() {
+                        "html" => redirects::RedirectFormat::Html,
+                        "netlify" => redirects::RedirectFormat::Netlify,
+                        other => return Err(format!("Invalid redirect format: {other}").into()),
+                    }
+                }
+                Long("csp") => {
+                    let output = match parser.value()?.string()?.as_str() {
+                        "meta" => csp::CspOutput::Meta,
+                        "header" => csp::CspOutput::Header,
+                        other => return Err(format!("Invalid CSP output: {other}").into()),
+                    };
+                    builder.csp_nonce(csp::random_nonce);
+                    csp = true;
+                    match output {
+                        csp::CspOutput::Meta => drop(builder.csp_meta()),
+                        csp::CspOutput::Header => csp_header = true,
+                    }
+                }
+                Long("trailing-slash") => {
+                    links.trailing_slash = match parser.value()?.string()?.as_str() {
+                        "add" => TrailingSlash::Add,
+                        "strip" => TrailingSlash::Strip,
+                        "keep" => TrailingSlash::Keep,
+                        other => return Err(format!("Invalid trailing slash mode: {other}").into()),
+                    }
+                }
+                Long("directory-links") => links.directory_index = true,
+                Long("text-whitespace") => {
+                    let mut whitespace = TextWhitespace::default();
+                    for option in parser.value()?.string()?.split(',') {
+                        match option.trim() {
+                            "trim" => whitespace.trim = true,
+                            "dedent" => whitespace.dedent = true,
+                            "collapse" => whitespace.collapse = true,
+                            other => {
+                                return Err(
+                                    format!("Invalid text whitespace option: {other}").into()
+                                )
+                            }
+                        }
+                    }
+                    builder.text_whitespace(whitespace);
+                }
+                #[cfg(feature = "a11y")]
+                Long("a11y") => drop(builder.add_plugin(htmeta_a11y::A11yPlugin::default())),
+                Long("critical-css") => {
+                    let path = PathBuf::from(parser.value()?);
+                    let css = critical_css::CriticalCss::load(&path).map_err(|e| {
+                        format!("Could not read the stylesheet {}: {e}", path.display())
+                    })?;
+                    builder.add_post_processor(css);
+                    critical_css = true;
+                }
+                Long("mode") => match parser.value()?.string()?.as_str() {
+                    "web" => drop(builder.mode(OutputMode::Web)),
+                    "email" => drop(builder.mode(OutputMode::Email)),
+                    other => return Err(format!("Invalid output mode: {other}").into()),
+                },
+                Long("check-links") => {
+                    let collector = check_links::LinkCollector::default();
+                    builder.add_plugin(collector.clone());
+                    link_collector = Some(collector);
+                }
+                Long("integrity") => integrity = true,
+                Long("minified-copy") => minified_filename = Some(PathBuf::from(parser.value()?)),
+                Long("root") => site_root = Some(PathBuf::from(parser.value()?)),
+                #[cfg(feature = "toc")]
+                Long("heading-anchors") => toc = toc.with_anchors(),
+                #[cfg(feature = "icons")]
+                Long("icons-dir") => icons_dir = Some(PathBuf::from(parser.value()?)),
                 Value(value) if input_filename.is_none() => {
                     input_filename = Some(PathBuf::from(value))
                 }
@@ -61,11 +205,57 @@ impl Args {
             }
         }
 
+        if csp && critical_css {
+            // Its inline `onload` handlers can't be allowed by a nonce
+            return Err("--critical-css can't be used with --csp".into());
+        }
+        builder.normalize_links(links);
+        let input_filename: PathBuf = input_filename.ok_or("Missing input filename")?;
+        // Local files are relative to where the page is written
+        let page = match &output_filename {
+            Some(output) if output != Path::new("-") => output,
+            _ => &input_filename,
+        };
+        let page_dir = page.parent().unwrap_or(Path::new("")).to_owned();
+        let site_root = site_root.unwrap_or_else(|| page_dir.clone());
+        if integrity {
+            builder.add_plugin(integrity::IntegrityPlugin::new(&site_root, page_dir));
+        }
+        let redirects = redirects::RedirectCollector::default();
+        builder.add_plugin(redirects.clone());
+        // Added after the plugins that check the tags emitted by it
+        #[cfg(feature = "templates")]
+        builder.add_plugin(htmeta_template::TemplatePlugin::default());
+
+        // Assets live next to the input document by default
+        #[cfg(any(feature = "icons", feature = "img"))]
+        let root = input_filename.parent().unwrap_or(Path::new(""));
+        #[cfg(feature = "icons")]
+        builder.add_plugin(htmeta_icons::IconPlugin::new(
+            icons_dir.unwrap_or_else(|| root.join("icons")),
+        ));
+        #[cfg(feature = "img")]
+        builder.add_plugin(htmeta_img::ImgPlugin::new(root));
+        #[cfg(feature = "toc")]
+        builder.add_plugin(toc);
+        #[cfg(feature = "footnotes")]
+        builder.add_plugin(htmeta_footnotes::FootnotePlugin::default());
+        #[cfg(feature = "highlight")]
+        builder.add_plugin(htmeta_highlight::HighlightPlugin::default());
+        #[cfg(feature = "og")]
+        builder.add_plugin(htmeta_og::OgPlugin);
+
         Ok({
             Args {
                 builder,
-                input_filename: input_filename.ok_or("Missing input filename")?,
+                input_filename,
                 output_filename,
+                minified_filename,
+                link_collector,
+                redirects,
+                redirect_format,
+                csp_header,
+                site_root,
             }
         })
     }
@@ -81,6 +271,117 @@ fn help(exename: &OsString) -> String {
     )
 }
 
+/// The input file, along with the plugins that have work left once a page is built.
+struct Source<'a> {
+    filename: &'a Path,
+    contents: &'a str,
+    uses_stdin: bool,
+    link_collector: Option<&'a check_links::LinkCollector>,
+    csp_header: bool,
+    /// Where links starting with `/` point to.
+    site_root: &'a Path,
+}
+
+/// Emits `doc` into `output`, or the standard output if it is `None`, and reports its
+/// warnings and broken links.
+fn build_page(
+    builder: &HtmlEmitterBuilder,
+    doc: &KdlDocument,
+    output: Option<&Path>,
+    minified_filename: Option<&Path>,
+    source: &Source,
+) -> miette::Result<()> {
+    let mut emitter = builder.build();
+    if !source.uses_stdin {
+        emitter.reset_for(source.filename);
+    }
+
+    let mut file: &mut dyn Write = match output {
+        // Write to file
+        Some(output) => {
+            let file = std::fs::File::create(output)
+                .into_diagnostic()
+                .with_context(|| format!("Could not create file {}.", output.display()))?;
+            &mut BufWriter::new(file)
+        }
+        // Dump to stdio
+        None => &mut std::io::stdout(),
+    };
+
+    match minified_filename {
+        Some(minified_filename) => {
+            let minified = std::fs::File::create(minified_filename).into_diagnostic()?;
+            let indent = emitter.indent;
+            emitter
+                .emit_multi(
+                    doc,
+                    &mut [
+                        (indent, &mut file),
+                        (Some(0), &mut BufWriter::new(minified)),
+                    ],
+                )
+                .into_diagnostic()?;
+        }
+        None => emitter.emit(doc, &mut file).into_diagnostic()?,
+    }
+    for warning in emitter.take_warnings() {
+        // Nodes from `_defaults.kdl` files have spans past the end of the page
+        let labels: Vec<_> = warning
+            .span
+            .filter(|span| span.end <= source.contents.len())
+            .map(LabeledSpan::underline)
+            .into_iter()
+            .collect();
+        let report = miette::miette!(
+            severity = Severity::Warning,
+            labels = labels,
+            "{}",
+            warning.message
+        )
+        .with_source_code(NamedSource::new(
+            source.filename.to_string_lossy(),
+            source.contents.to_string(),
+        ));
+        eprintln!("{report:?}");
+    }
+
+    let policy = emitter
+        .content_security_policy()
+        .filter(|_| source.csp_header);
+    if let (Some(output), Some(policy)) = (output, policy) {
+        csp::write_header(output, &policy)
+            .into_diagnostic()
+            .with_context(|| format!("Could not write the headers of {}.", output.display()))?;
+    }
+
+    // Links are relative to where the page is written
+    let page = output.unwrap_or(source.filename);
+    let root = page.parent().unwrap_or(Path::new(""));
+    if let Some(collector) = source.link_collector {
+        let broken = collector.check(source.site_root, root, source.filename, source.contents);
+        for report in &broken {
+            eprintln!("{report:?}");
+        }
+        if !broken.is_empty() {
+            return Err(miette::miette!("Found {} broken links.", broken.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Removes the command, like `test`, from the start of `args` and returns it, if present.
+fn take_command(args: &mut Vec<OsString>) -> Option<String> {
+    let command = args
+        .first()
+        .and_then(|arg| arg.to_str())
+        .filter(|arg| ["test", "diff"].contains(arg))
+        .map(str::to_string);
+    if command.is_some() {
+        args.remove(0);
+    }
+    command
+}
+
 fn main() -> miette::Result<()> {
     let mut args: Vec<_> = std::env::args_os().collect();
     let exename = args.remove(0);
@@ -94,12 +395,31 @@ fn main() -> miette::Result<()> {
         return Ok(());
     }
 
+    // The command is taken first, as profile flags go before the other ones
+    let command = take_command(&mut args);
+    profile::expand(&mut args, Path::new(profile::CONFIG_FILENAME))?;
+
     let Args {
         builder,
         input_filename,
         output_filename,
+        minified_filename,
+        link_collector,
+        redirects,
+        redirect_format,
+        csp_header,
+        site_root,
     } = Args::parse(args).map_err(|cause| CliError { exename, cause })?;
 
+    match command.as_deref() {
+        Some("test") => return component_tests::run(&builder, &input_filename),
+        Some("diff") => {
+            let existing = output_filename.unwrap_or_else(|| input_filename.with_extension("html"));
+            return html_diff::run(&builder, &input_filename, &existing);
+        }
+        _ => (),
+    }
+
     let mut uses_stdin = false;
     let contents = if input_filename == Path::new("-") {
         uses_stdin = true;
@@ -113,21 +433,80 @@ fn main() -> miette::Result<()> {
             .into_diagnostic()
             .with_context(|| format!("Could not open file {}.", input_filename.display()))?
     };
-    let doc = contents.parse::<KdlDocument>()?;
-    let mut emitter = builder.build();
+    // Documents written in KDL v1 keep working
+    let mut doc = htmeta::parse_document(&contents)?;
+    if !uses_stdin {
+        defaults::apply(&mut doc, &input_filename)?;
+    }
+    let source = Source {
+        filename: &input_filename,
+        contents: &contents,
+        uses_stdin,
+        link_collector: link_collector.as_ref(),
+        csp_header,
+        site_root: &site_root,
+    };
 
-    // Dump to stdio
-    let mut file: &mut dyn Write = if uses_stdin || output_filename == Some("-".into()) {
-        &mut std::io::stdout()
-    // Write to file
+    let uses_stdout = uses_stdin || output_filename == Some("-".into());
+    let output_filename = output_filename.unwrap_or_else(|| input_filename.with_extension("html"));
+    let documents = htmeta::split_documents(&doc).into_diagnostic()?;
+    let mut pages = Vec::new();
+    if documents.is_empty() {
+        let output = (!uses_stdout).then_some(output_filename.as_path());
+        build_page(
+            &builder,
+            &doc,
+            output,
+            minified_filename.as_deref(),
+            &source,
+        )?;
+        pages.extend(output.map(Path::to_path_buf));
+        pages.extend(minified_filename);
     } else {
-        let file = std::fs::File::create(
-            output_filename.unwrap_or_else(|| input_filename.with_extension("html")),
-        )
-        .into_diagnostic()?;
-        &mut BufWriter::new(file)
-    };
+        // Each `@document` is written next to where the file's output would be
+        if uses_stdout {
+            return Err(miette::miette!(
+                "Files with `@document` nodes can't be written to the standard output."
+            ));
+        }
+        if minified_filename.is_some() {
+            return Err(miette::miette!(
+                "--minified-copy can't be used with files with `@document` nodes."
+            ));
+        }
+        let root = output_filename.parent().unwrap_or(Path::new(""));
+        for (path, document) in &documents {
+            let page = root.join(path);
+            build_page(&builder, document, Some(&page), None, &source)?;
+            pages.push(page);
+        }
+    }
+    let pages: Vec<_> = pages.iter().map(PathBuf::as_path).collect();
+    redirects
+        .write(&site_root, redirect_format, &input_filename, &pages)
+        .context("Could not write the redirects.")
+}
 
-    emitter.emit(&doc, &mut file).into_diagnostic()?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_with_profile() {
+        let dir = std::env::temp_dir().join(format!("htmeta-command-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join(profile::CONFIG_FILENAME);
+        std::fs::write(&config, r#"profile "ci" { minify; define "ci" }"#).unwrap();
+
+        let mut args: Vec<OsString> = ["test", "--profile", "ci", "x.kdl"]
+            .into_iter()
+            .map(OsString::from)
+            .collect();
+        let command = take_command(&mut args);
+        let expanded = profile::expand(&mut args, &config);
+        std::fs::remove_dir_all(&dir).unwrap();
+        expanded.unwrap();
+        htmeta_auto_test::assert_eq!(command.as_deref(), Some("test"));
+        htmeta_auto_test::assert_eq!(args, ["--minify", "--define=ci", "x.kdl"]);
+    }
 }
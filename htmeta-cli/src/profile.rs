@@ -0,0 +1,146 @@
+//! Named sets of options, loaded from the project's config file.
+//!
+//! ```kdl
+//! profile "prod" {
+//!     minify
+//!     define "analytics"
+//! }
+//! ```
+//!
+//! Each node inside a profile is the long name of a command line flag, and its arguments
+//! are the flag's values.
+
+use std::{ffi::OsString, path::Path};
+
+use htmeta::kdl::KdlValue;
+use miette::{Context, IntoDiagnostic};
+
+/// The project config file, looked up in the working directory.
+pub const CONFIG_FILENAME: &str = "htmeta.config.kdl";
+
+/// Removes `--profile NAME` from `args` and returns `NAME`, if present. Fails if `NAME` is
+/// missing.
+fn take_profile_name(args: &mut Vec<OsString>) -> miette::Result<Option<OsString>> {
+    let Some(i) = args
+        .iter()
+        .position(|arg| arg == "--profile" || arg.to_string_lossy().starts_with("--profile="))
+    else {
+        return Ok(None);
+    };
+    let arg = args.remove(i);
+    let name = match arg.to_string_lossy().strip_prefix("--profile=") {
+        Some(name) => name.into(),
+        None if i < args.len() => args.remove(i),
+        None => OsString::new(),
+    };
+    match name.is_empty() {
+        true => Err(miette::miette!(
+            "Missing the name of the profile for `--profile`."
+        )),
+        false => Ok(Some(name)),
+    }
+}
+
+/// Replaces `--profile NAME` in `args` with the flags of that profile, from the config file
+/// at `config`. They go first, so the other flags can override them. `args` must not
+/// start with a command, like `test`, as they would go before it.
+pub fn expand(args: &mut Vec<OsString>, config: &Path) -> miette::Result<()> {
+    if let Some(name) = take_profile_name(args)? {
+        let mut flags = load_profile(config, &name.to_string_lossy())?;
+        flags.append(args);
+        *args = flags;
+    }
+    Ok(())
+}
+
+/// Returns the command line flags of the profile named `name`, from the config file at
+/// `path`.
+fn load_profile(path: &Path, name: &str) -> miette::Result<Vec<OsString>> {
+    let contents = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .with_context(|| format!("Could not open the config file {}.", path.display()))?;
+    let config = htmeta::parse_document(&contents)?;
+    let profile = config
+        .nodes()
+        .iter()
+        .find(|node| {
+            node.name().value() == "profile"
+                && node.get(0).and_then(KdlValue::as_string) == Some(name)
+        })
+        .ok_or_else(|| miette::miette!("No profile named `{name}` in {}.", path.display()))?;
+
+    let mut flags = Vec::new();
+    for option in profile
+        .children()
+        .map(|doc| doc.nodes())
+        .unwrap_or_default()
+    {
+        let flag = format!("--{}", option.name().value());
+        let values: Vec<_> = option
+            .entries()
+            .iter()
+            .filter(|entry| entry.name().is_none())
+            .map(|entry| match entry.value() {
+                KdlValue::String(value) => value.clone(),
+                value => value.to_string(),
+            })
+            .collect();
+        if values.is_empty() {
+            flags.push(flag.into());
+        } else {
+            flags.extend(values.iter().map(|value| format!("{flag}={value}").into()));
+        }
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expands `args` with a config file in a directory named after `test`.
+    fn expanded(test: &str, args: &[&str]) -> miette::Result<Vec<OsString>> {
+        let dir = std::env::temp_dir().join(format!("htmeta-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join(CONFIG_FILENAME);
+        std::fs::write(&config, r#"profile "prod" { minify; define "a" "b" }"#).unwrap();
+        let mut args = args.iter().map(OsString::from).collect();
+        expand(&mut args, &config)?;
+        Ok(args)
+    }
+
+    #[test]
+    fn profile_flags() {
+        let flags = ["--minify", "--define=a", "--define=b", "x.kdl"].map(OsString::from);
+        htmeta_auto_test::assert_eq!(
+            expanded("profile-flags", &["--profile", "prod", "x.kdl"]).unwrap(),
+            flags
+        );
+        htmeta_auto_test::assert_eq!(
+            expanded("profile-flags", &["x.kdl", "--profile=prod"]).unwrap(),
+            flags
+        );
+        htmeta_auto_test::assert_eq!(
+            expanded("profile-flags", &["x.kdl"]).unwrap(),
+            [OsString::from("x.kdl")]
+        );
+    }
+
+    #[test]
+    fn missing_profile_name() {
+        for args in [&["x.kdl", "--profile"][..], &["--profile=", "x.kdl"]] {
+            htmeta_auto_test::assert_eq!(
+                expanded("missing-profile-name", args)
+                    .unwrap_err()
+                    .to_string(),
+                "Missing the name of the profile for `--profile`."
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let error = expanded("unknown-profile", &["--profile", "dev", "x.kdl"]).unwrap_err();
+        assert!(error.to_string().starts_with("No profile named `dev` in "));
+    }
+}
@@ -0,0 +1,316 @@
+//! Redirects declared with `@redirect`, written as pages or a `_redirects` file after
+//! building.
+
+use std::{
+    cell::RefCell,
+    path::{Component, Path},
+    rc::Rc,
+};
+
+use htmeta::{kdl::KdlNode, EmitResult, EmitStatus, Error, IPlugin, PluginContext};
+use miette::{Context, IntoDiagnostic};
+
+/// How redirects are written.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum RedirectFormat {
+    /// A page for each redirect, which sends browsers to its target with a `meta` refresh.
+    #[default]
+    Html,
+    /// Lines in a Netlify style `_redirects` file, like `/old /new 301`.
+    Netlify,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Redirect {
+    from: String,
+    to: String,
+    status: u16,
+}
+
+/// Collects `@redirect from="/old" to="/new"` nodes, which emit nothing, so they can be
+/// written once every page is built. `status` sets the redirect's status code, which
+/// defaults to `301`.
+#[derive(Debug, Clone, Default)]
+pub struct RedirectCollector {
+    // Shared between every copy of the plugin, as nested nodes are emitted by other emitters.
+    // Kept across pages, so `_redirects` gets those of every page built.
+    redirects: Rc<RefCell<Vec<Redirect>>>,
+}
+
+/// Escapes `text` so it can be used inside a double quoted attribute.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+}
+
+/// Marks the start of the lines of `_redirects` that come from a source file.
+const SECTION: &str = "# htmeta: ";
+
+/// Returns `existing`, the contents of a `_redirects` file, with the lines that came from
+/// `source` replaced by `lines`. Other lines, like those of other pages or written by hand,
+/// are kept.
+fn merge_netlify(existing: &str, source: &str, lines: &[String]) -> String {
+    let mut merged = String::new();
+    let mut owner: Option<&str> = None;
+    for line in existing.lines() {
+        if let Some(name) = line.strip_prefix(SECTION) {
+            owner = Some(name);
+        } else if line.trim().is_empty() {
+            owner = None;
+        }
+        // Removed sections leave their separating blank lines behind
+        let is_extra_blank =
+            line.trim().is_empty() && (merged.is_empty() || merged.ends_with("\n\n"));
+        if owner != Some(source) && !is_extra_blank {
+            merged.push_str(line);
+            merged.push('\n');
+        }
+    }
+    if lines.is_empty() {
+        return merged;
+    }
+    if !merged.is_empty() && !merged.ends_with("\n\n") {
+        merged.push('\n');
+    }
+    merged.push_str(&format!("{SECTION}{source}\n"));
+    for line in lines {
+        merged.push_str(line);
+        merged.push('\n');
+    }
+    merged
+}
+
+impl RedirectCollector {
+    /// Writes the redirects collected from `source` in `format`, relative to `root`, the
+    /// site's root. `pages` are the pages that were built, which redirects can't replace.
+    ///
+    /// Lines of `_redirects` are grouped by the file they come from, so building a page
+    /// only replaces its own redirects.
+    pub fn write(
+        &self,
+        root: &Path,
+        format: RedirectFormat,
+        source: &Path,
+        pages: &[&Path],
+    ) -> miette::Result<()> {
+        let redirects = self.redirects.borrow();
+        match format {
+            RedirectFormat::Html => {
+                let pages: Vec<_> = pages
+                    .iter()
+                    .filter_map(|page| page.canonicalize().ok())
+                    .collect();
+                for redirect in redirects.iter() {
+                    let path = root.join(redirect.from.trim_start_matches('/'));
+                    let path = match path.extension() {
+                        Some(_) => path,
+                        None => path.join("index.html"),
+                    };
+                    if path.canonicalize().is_ok_and(|path| pages.contains(&path)) {
+                        return Err(miette::miette!(
+                            "The redirect from {} would replace the page at {}.",
+                            redirect.from,
+                            path.display()
+                        ));
+                    }
+                    if let Some(parent) = path.parent() {
+                        std::fs::create_dir_all(parent).into_diagnostic()?;
+                    }
+                    std::fs::write(&path, redirect_page(&redirect.to))
+                        .into_diagnostic()
+                        .with_context(|| format!("Could not write {}.", path.display()))?;
+                }
+            }
+            RedirectFormat::Netlify => {
+                let path = root.join("_redirects");
+                let existing = match std::fs::read_to_string(&path) {
+                    Ok(existing) => existing,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+                    Err(err) => {
+                        return Err(err)
+                            .into_diagnostic()
+                            .with_context(|| format!("Could not read {}.", path.display()));
+                    }
+                };
+                let lines: Vec<_> = redirects
+                    .iter()
+                    .map(|redirect| {
+                        format!("{} {} {}", redirect.from, redirect.to, redirect.status)
+                    })
+                    .collect();
+                let merged = merge_netlify(&existing, &source.to_string_lossy(), &lines);
+                if merged != existing {
+                    std::fs::write(&path, merged)
+                        .into_diagnostic()
+                        .with_context(|| format!("Could not write {}.", path.display()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns a page that redirects browsers to `to`.
+fn redirect_page(to: &str) -> String {
+    let to = escape(to);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+    <head>
+        <meta charset="utf-8">
+        <title>Redirecting…</title>
+        <link rel="canonical" href="{to}">
+        <meta http-equiv="refresh" content="0; url={to}">
+    </head>
+    <body>
+        <a href="{to}">Redirecting to {to}</a>
+    </body>
+</html>
+"#
+    )
+}
+
+impl IPlugin for RedirectCollector {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        if node.name().value() != "@redirect" {
+            return Ok(EmitStatus::Skip);
+        }
+        let vars = &context.emitter.vars;
+        let property = |key: &str| {
+            node.get(key)
+                .map(|value| vars.expand_value(value).into_owned())
+                .ok_or_else(|| Error::MissingProperty {
+                    node: "@redirect".into(),
+                    property: key.into(),
+                })
+        };
+        let status = match node.get("status") {
+            Some(_) => property("status")?
+                .parse()
+                .ok()
+                .filter(|status| (300..400).contains(status))
+                .ok_or_else(|| Error::InvalidProperty {
+                    node: "@redirect".into(),
+                    property: "status".into(),
+                    expected: "a redirect status code, like 301 or 302".into(),
+                })?,
+            None => 301,
+        };
+        let from = property("from")?;
+        // Like `@document` paths, redirects can't point outside of the site
+        let is_inside = Path::new(from.trim_start_matches('/'))
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !is_inside {
+            return Err(Error::InvalidProperty {
+                node: "@redirect".into(),
+                property: "from".into(),
+                expected: "a path inside the site, without `..`".into(),
+            });
+        }
+        let redirect = Redirect {
+            from,
+            to: property("to")?,
+            status,
+        };
+        // Pages are emitted twice with `--minified-copy`
+        let mut redirects = self.redirects.borrow_mut();
+        if !redirects.contains(&redirect) {
+            redirects.push(redirect);
+        }
+        Ok(EmitStatus::Emmited)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use htmeta::HtmlEmitter;
+
+    fn emit(collector: &RedirectCollector, source: &str) -> EmitResult {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(collector.clone());
+        builder
+            .build()
+            .emit(&source.parse().unwrap(), &mut Vec::new())
+    }
+
+    /// Returns a new, empty directory for a test.
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("htmeta-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn invalid_redirects() {
+        let collector = RedirectCollector::default();
+        htmeta_auto_test::assert_eq!(
+            emit(&collector, r#"@redirect from="/a" to="/b" status=200"#),
+            Err(Error::InvalidProperty {
+                node: "@redirect".into(),
+                property: "status".into(),
+                expected: "a redirect status code, like 301 or 302".into(),
+            })
+        );
+        for from in ["/../outside.html", "/blog/./../../x"] {
+            htmeta_auto_test::assert_eq!(
+                emit(&collector, &format!(r#"@redirect from="{from}" to="/b""#)),
+                Err(Error::InvalidProperty {
+                    node: "@redirect".into(),
+                    property: "from".into(),
+                    expected: "a path inside the site, without `..`".into(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn html_redirects_keep_pages() {
+        let root = temp_dir("html-redirects");
+        let page = root.join("index.html");
+        std::fs::write(&page, "<p>Home</p>").unwrap();
+        let collector = RedirectCollector::default();
+        emit(&collector, r#"@redirect from="/index.html" to="/home""#).unwrap();
+        let result = collector.write(
+            &root,
+            RedirectFormat::Html,
+            Path::new("index.kdl"),
+            &[&page],
+        );
+        let contents = std::fs::read_to_string(&page).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(result.is_err());
+        htmeta_auto_test::assert_eq!(contents, "<p>Home</p>");
+    }
+
+    #[test]
+    fn netlify_redirects() {
+        let root = temp_dir("netlify-redirects");
+        std::fs::write(root.join("_redirects"), "/manual /kept 302\n").unwrap();
+        let build = |source: &str, nodes: &str| {
+            let collector = RedirectCollector::default();
+            emit(&collector, nodes).unwrap();
+            collector
+                .write(&root, RedirectFormat::Netlify, Path::new(source), &[])
+                .unwrap();
+            std::fs::read_to_string(root.join("_redirects")).unwrap()
+        };
+        build("a.kdl", r#"@redirect from="/old" to="/a""#);
+        build("b.kdl", r#"@redirect from="/blog" to="/posts" status=302"#);
+        // Pages without redirects leave the others alone
+        build("c.kdl", "p");
+        let written = build("a.kdl", r#"@redirect from="/older" to="/a""#);
+        std::fs::remove_dir_all(&root).unwrap();
+        htmeta_auto_test::assert_eq!(
+            written,
+            concat!(
+                "/manual /kept 302\n\n",
+                "# htmeta: b.kdl\n/blog /posts 302\n\n",
+                "# htmeta: a.kdl\n/older /a 301\n"
+            )
+        );
+    }
+}
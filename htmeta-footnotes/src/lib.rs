@@ -0,0 +1,131 @@
+use std::{cell::RefCell, rc::Rc};
+
+use htmeta::{kdl::KdlNode, EmitInfo, EmitResult, EmitStatus, Error, IPlugin, PluginContext, Vars};
+
+#[derive(Debug, Default)]
+struct FootnoteState {
+    /// How many footnotes were referenced so far.
+    count: usize,
+    /// Escaped text of the footnotes that weren't listed yet, by number.
+    pending: Vec<(usize, String)>,
+}
+
+/// Adds footnotes to `htmeta`.
+///
+/// `@footnote "text"` emits a numbered reference to the note, and `@footnotes` lists every
+/// note referenced since the last list, with links back to their references. Notes that
+/// weren't listed are listed at the end of the `body`, or of the document if it has none.
+///
+/// ```kdl
+/// p {
+///     - "The earth is round."
+///     @footnote "Citation needed."
+/// }
+/// @footnotes
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FootnotePlugin {
+    // Shared between every copy of the plugin, so notes are numbered across the document.
+    state: Rc<RefCell<FootnoteState>>,
+}
+
+impl FootnotePlugin {
+    fn emit_reference(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        let emitter = context.emitter;
+        let text = node.get(0).ok_or_else(|| Error::MissingArgument {
+            node: "@footnote".into(),
+            argument: "text".into(),
+        })?;
+        let text = emitter
+            .escape_text(&emitter.vars.expand_value(text))
+            .into_owned();
+
+        let mut state = self.state.borrow_mut();
+        state.count += 1;
+        let number = state.count;
+        state.pending.push((number, text));
+
+        write!(
+            context.writer,
+            r##"{}<sup id="fnref-{number}"><a href="#fn-{number}">{number}</a></sup>"##,
+            context.indent
+        )?;
+        emitter.write_line(context.writer)?;
+        Ok(EmitStatus::Emmited)
+    }
+
+    /// Lists the pending footnotes, if there are any.
+    fn emit_list(&self, context: PluginContext) -> EmitResult {
+        let mut state = self.state.borrow_mut();
+        let Some(&(first, _)) = state.pending.first() else {
+            return Ok(());
+        };
+        let emitter = context.emitter;
+        let writer = context.writer;
+        let indent = context.indent;
+        let inner = " ".repeat(emitter.indent.unwrap_or(4));
+
+        // Keeps numbering going after an earlier list
+        let start = match first {
+            1 => String::new(),
+            first => format!(r#" start="{first}""#),
+        };
+        write!(writer, r#"{indent}<ol class="footnotes"{start}>"#)?;
+        emitter.write_line(writer)?;
+        for (number, text) in std::mem::take(&mut state.pending) {
+            write!(
+                writer,
+                r##"{indent}{inner}<li id="fn-{number}">{text} <a href="#fnref-{number}">↩</a></li>"##
+            )?;
+            emitter.write_line(writer)?;
+        }
+        write!(writer, "{indent}</ol>")?;
+        emitter.write_line(writer)?;
+        Ok(())
+    }
+}
+
+impl IPlugin for FootnotePlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        match node.name().value() {
+            "@footnote" => self.emit_reference(node, context),
+            "@footnotes" => {
+                self.emit_list(context)?;
+                Ok(EmitStatus::Emmited)
+            }
+            _ => Ok(EmitStatus::Skip),
+        }
+    }
+
+    fn on_start(&self, _: &mut Vars, _: &EmitInfo) -> EmitResult {
+        // Numbering starts over for each document
+        *self.state.borrow_mut() = FootnoteState::default();
+        Ok(())
+    }
+
+    fn on_body_end(&self, context: PluginContext) -> EmitResult {
+        self.emit_list(context)
+    }
+
+    fn on_end(&self, context: PluginContext) -> EmitResult {
+        // Documents without a `body`, like fragments
+        self.emit_list(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use htmeta::emit_as_str;
+    use htmeta::{HtmlEmitter, HtmlEmitterBuilder};
+    use htmeta_auto_test::*;
+
+    fn builder() -> HtmlEmitterBuilder {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(FootnotePlugin::default());
+        builder
+    }
+
+    auto_html_test!(footnotes_test, builder());
+    auto_html_test_fail!(fail_missing_text, builder());
+}
@@ -0,0 +1,234 @@
+use htmeta::{kdl::KdlNode, EmitResult, EmitStatus, Error, IPlugin, PluginContext};
+
+/// Adds a `@code` command for code blocks, highlighted at build time.
+///
+/// The code is either the node's argument or its text and raw (`_`) children, one per
+/// line. It is emitted inside `<pre><code class="language-rust">`, and variables are not
+/// expanded in it.
+///
+/// ```kdl
+/// @code lang="rust" {
+///     - "fn main() {"
+///     - #"    println!("Hello, world!");"#
+///     - "}"
+/// }
+/// ```
+///
+/// Highlighting requires the `syntect` feature. Without it, code is emitted as-is, which
+/// still works with client-side highlighters that look at the `language-*` class.
+#[derive(Debug, Clone)]
+pub struct HighlightPlugin {
+    #[cfg_attr(not(feature = "syntect"), allow(dead_code))]
+    theme: String,
+}
+
+impl Default for HighlightPlugin {
+    fn default() -> Self {
+        Self {
+            theme: "InspiredGitHub".into(),
+        }
+    }
+}
+
+impl HighlightPlugin {
+    /// Uses the `syntect` theme called `theme`. The default is `InspiredGitHub`.
+    pub fn with_theme(mut self, theme: impl Into<String>) -> Self {
+        self.theme = theme.into();
+        self
+    }
+
+    #[cfg(feature = "syntect")]
+    fn highlight(&self, code: &str, lang: Option<&str>) -> EmitResult<String> {
+        use std::sync::LazyLock;
+        use syntect::{
+            easy::HighlightLines,
+            highlighting::ThemeSet,
+            html::{styled_line_to_highlighted_html, IncludeBackground},
+            parsing::SyntaxSet,
+            util::LinesWithEndings,
+        };
+
+        static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+        static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+        let theme = THEMES
+            .themes
+            .get(&self.theme)
+            .ok_or_else(|| HighlightError::UnknownTheme {
+                theme: self.theme.clone(),
+            })?;
+        let syntax = lang
+            .and_then(|lang| SYNTAXES.find_syntax_by_token(lang))
+            .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        let failed = |e: syntect::Error| HighlightError::Failed {
+            message: e.to_string(),
+        };
+        let mut html = String::new();
+        for line in LinesWithEndings::from(code) {
+            let regions = highlighter
+                .highlight_line(line, &SYNTAXES)
+                .map_err(failed)?;
+            html.push_str(
+                &styled_line_to_highlighted_html(&regions, IncludeBackground::No)
+                    .map_err(failed)?,
+            );
+        }
+        Ok(html)
+    }
+
+    #[cfg(not(feature = "syntect"))]
+    fn highlight(&self, code: &str, _lang: Option<&str>) -> EmitResult<String> {
+        Ok(html_escape::encode_text(code).into_owned())
+    }
+}
+
+/// Errors of [`HighlightPlugin`], reported as [`Error::Plugin`].
+#[derive(Debug, PartialEq)]
+pub enum HighlightError {
+    /// The plugin's theme doesn't exist.
+    UnknownTheme { theme: String },
+    /// Code couldn't be highlighted.
+    Failed { message: String },
+}
+
+impl std::fmt::Display for HighlightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HighlightError::UnknownTheme { theme } => write!(f, "Unknown theme `{theme}`!"),
+            HighlightError::Failed { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for HighlightError {}
+
+impl From<HighlightError> for Error {
+    fn from(error: HighlightError) -> Self {
+        Error::Plugin {
+            node: "@code".into(),
+            error: Box::new(error),
+        }
+    }
+}
+
+/// Returns the first argument of `node`, which must be a string.
+fn string_argument<'a>(node: &'a KdlNode, argument: &str) -> EmitResult<&'a str> {
+    let name = node.name().value();
+    node.get(0)
+        .ok_or_else(|| Error::MissingArgument {
+            node: name.into(),
+            argument: argument.into(),
+        })?
+        .as_string()
+        .ok_or_else(|| Error::InvalidArgument {
+            node: name.into(),
+            argument: argument.into(),
+            expected: "a string".into(),
+        })
+}
+
+/// Returns the code inside a `@code` node.
+fn code(node: &KdlNode) -> EmitResult<String> {
+    if node.get(0).is_some() {
+        return string_argument(node, "code").map(Into::into);
+    }
+    let lines = node
+        .children()
+        .ok_or_else(|| Error::MissingChildren {
+            node: "@code".into(),
+        })?
+        .nodes()
+        .iter()
+        .map(|line| match line.name().value() {
+            "-" => string_argument(line, "text"),
+            "_" => string_argument(line, "code"),
+            child => Err(Error::UnexpectedChild {
+                node: "@code".into(),
+                child: child.into(),
+            }),
+        })
+        .collect::<EmitResult<Vec<_>>>()?;
+    Ok(lines.join("\n"))
+}
+
+impl IPlugin for HighlightPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        if node.name().value() != "@code" {
+            return Ok(EmitStatus::Skip);
+        }
+        let lang = node
+            .get("lang")
+            .map(|lang| context.emitter.vars.expand_value(lang));
+        let code = self.highlight(code(node)?.trim_matches('\n'), lang.as_deref())?;
+        let class = match &lang {
+            Some(lang) => format!(
+                r#" class="language-{}""#,
+                html_escape::encode_double_quoted_attribute(lang)
+            ),
+            None => String::new(),
+        };
+        // Whitespace matters inside `pre`, so the code isn't indented
+        write!(
+            context.writer,
+            "{}<pre><code{class}>{code}</code></pre>",
+            context.indent
+        )?;
+        context.emitter.write_line(context.writer)?;
+        Ok(EmitStatus::Emmited)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use htmeta::emit_as_str;
+    use htmeta::{HtmlEmitter, HtmlEmitterBuilder};
+    use htmeta_auto_test::*;
+
+    fn builder() -> HtmlEmitterBuilder {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(HighlightPlugin::default());
+        builder
+    }
+
+    #[cfg(not(feature = "syntect"))]
+    auto_html_test!(plain_code, builder());
+    #[cfg(feature = "syntect")]
+    auto_html_test!(highlighted_code, builder());
+    auto_html_test_fail!(fail_empty_code, builder());
+    auto_html_test_fail!(fail_code_number, builder());
+    auto_html_test_fail!(fail_code_child, builder());
+
+    #[test]
+    fn raw_code() {
+        let mut builder = builder();
+        builder.minify();
+        let html = emit_as_str(
+            &builder,
+            r#"@code {
+                - "<b>"
+                _ "</b>"
+            }"#,
+        )
+        .unwrap();
+        assert!(html.contains("&lt;/b&gt;"));
+    }
+
+    #[test]
+    fn unknown_theme() {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(HighlightPlugin::default().with_theme("Nope"));
+        let result = emit_as_str(&builder, r#"@code "x""#);
+        #[cfg(feature = "syntect")]
+        htmeta_auto_test::assert_eq!(
+            result,
+            Err(HighlightError::UnknownTheme {
+                theme: "Nope".into()
+            }
+            .into())
+        );
+        #[cfg(not(feature = "syntect"))]
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,230 @@
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, rc::Rc, sync::LazyLock};
+
+use htmeta::{
+    kdl::KdlNode, kdl_ext::KdlNodeExt, EmitInfo, EmitResult, EmitStatus, Error, FileProvider,
+    IPlugin, PluginContext, Vars,
+};
+use regex::Regex;
+
+static SVG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<svg\b([^>]*)>(.*)</svg>").unwrap());
+static VIEW_BOX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"viewBox\s*=\s*"([^"]*)""#).unwrap());
+static ICON_NAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\w-]+$").unwrap());
+
+#[derive(Debug, Default)]
+struct SpriteState {
+    /// `<symbol>` markup of every icon loaded so far, by name.
+    symbols: HashMap<String, String>,
+    /// Icons used by the current document, in order of first use.
+    used: Vec<String>,
+}
+
+/// Adds an `@icon "name"` command that references `name.svg` from an icon directory
+/// through an `SVG` sprite, instead of inlining the whole icon every time.
+///
+/// Each icon file is loaded only once. Properties given to `@icon` become attributes of the
+/// emitted `<svg>` element, and the sprite holding every used icon is emitted at the end
+/// of the `body`, or of the document if it has none.
+///
+/// ```kdl
+/// button {
+///     @icon "github" class="icon"
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct IconPlugin {
+    dir: PathBuf,
+    // Shared between every copy of the plugin, so nested nodes register their icons in the
+    // same sprite.
+    state: Rc<RefCell<SpriteState>>,
+}
+
+impl IconPlugin {
+    /// Returns a new [`Self`] that loads icons from the `dir` directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            state: Default::default(),
+        }
+    }
+
+    /// Loads the icon called `name` from `files` as a `<symbol>` unless it was already loaded.
+    fn load(&self, name: &str, files: &dyn FileProvider) -> EmitResult {
+        let mut state = self.state.borrow_mut();
+        if !state.symbols.contains_key(name) {
+            let path = self.dir.join(name).with_extension("svg");
+            let svg = files
+                .read_to_string(&path)
+                .map_err(|error| Error::ReadFile {
+                    node: "@icon".into(),
+                    path: path.display().to_string(),
+                    error,
+                })?;
+            let captures = SVG.captures(&svg).ok_or_else(|| Error::InvalidFile {
+                node: "@icon".into(),
+                path: path.display().to_string(),
+                reason: "is not an svg file".into(),
+            })?;
+            let view_box = VIEW_BOX
+                .captures(&captures[1])
+                .map(|view_box| format!(r#" viewBox="{}""#, &view_box[1]))
+                .unwrap_or_default();
+            let symbol = format!(
+                r#"<symbol id="icon-{name}"{view_box}>{}</symbol>"#,
+                captures[2].trim()
+            );
+            state.symbols.insert(name.into(), symbol);
+        }
+        if !state.used.iter().any(|used| used == name) {
+            state.used.push(name.into());
+        }
+        Ok(())
+    }
+
+    /// Emits the sprite with every icon used since it was last emitted, if any.
+    fn emit_sprite(&self, context: PluginContext) -> EmitResult {
+        let mut state = self.state.borrow_mut();
+        if state.used.is_empty() {
+            return Ok(());
+        }
+        let emitter = context.emitter;
+        let writer = context.writer;
+        let indent = " ".repeat(emitter.indent.unwrap_or(4));
+        write!(
+            writer,
+            r#"{}<svg xmlns="http://www.w3.org/2000/svg" style="display:none">"#,
+            context.indent
+        )?;
+        emitter.write_line(writer)?;
+        for name in std::mem::take(&mut state.used) {
+            write!(writer, "{}{indent}{}", context.indent, state.symbols[&name])?;
+            emitter.write_line(writer)?;
+        }
+        write!(writer, "{}</svg>", context.indent)?;
+        emitter.write_line(writer)?;
+        Ok(())
+    }
+}
+
+impl IPlugin for IconPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        if node.command_name() != Some("icon") {
+            return Ok(EmitStatus::Skip);
+        }
+        let vars = &context.emitter.vars;
+        let name = node.get(0).ok_or_else(|| Error::MissingArgument {
+            node: "@icon".into(),
+            argument: "name".into(),
+        })?;
+        let name = vars.expand_value(name);
+        if !ICON_NAME.is_match(&name) {
+            return Err(Error::InvalidArgument {
+                node: "@icon".into(),
+                argument: "name".into(),
+                expected: "made of letters, digits, `-` and `_`".into(),
+            });
+        }
+        self.load(&name, context.emitter.files())?;
+
+        let writer = context.writer;
+        write!(writer, "{}<svg", context.indent)?;
+        for (key, value) in node.keyed_entries() {
+            write!(
+                writer,
+                r#" {key}="{}""#,
+                html_escape::encode_double_quoted_attribute(&vars.expand_value(value))
+            )?;
+        }
+        write!(writer, r##"><use href="#icon-{name}"></use></svg>"##)?;
+        context.emitter.write_line(writer)?;
+        Ok(EmitStatus::Emmited)
+    }
+
+    fn on_start(&self, _: &mut Vars, _: &EmitInfo) -> EmitResult {
+        // Icons used by a document that failed are left over
+        self.state.borrow_mut().used.clear();
+        Ok(())
+    }
+
+    fn on_body_end(&self, context: PluginContext) -> EmitResult {
+        self.emit_sprite(context)
+    }
+
+    fn on_end(&self, context: PluginContext) -> EmitResult {
+        // Documents without a `body`, like fragments
+        self.emit_sprite(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use htmeta::emit_as_str;
+    use htmeta::{HtmlEmitter, HtmlEmitterBuilder};
+    use htmeta_auto_test::*;
+
+    fn builder() -> HtmlEmitterBuilder {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(IconPlugin::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/icons"
+        )));
+        builder
+    }
+
+    auto_html_test!(sprite_test, builder());
+    auto_html_test_fail!(fail_missing_name, builder());
+    auto_html_test_fail!(fail_invalid_name, builder());
+
+    #[test]
+    fn missing_icon() {
+        let error = emit_as_str(&builder(), r#"@icon "gone""#).unwrap_err();
+        assert!(matches!(error, Error::ReadFile { node, .. } if node == "@icon"));
+    }
+
+    #[test]
+    fn not_svg() {
+        let mut files = htmeta::MemoryFiles::new();
+        files.insert("icons/broken.svg", "This is not an icon.");
+        let mut builder = HtmlEmitter::builder();
+        builder.files(files).add_plugin(IconPlugin::new("icons"));
+        htmeta_auto_test::assert_eq!(
+            emit_as_str(&builder, r#"@icon "broken""#),
+            Err(Error::InvalidFile {
+                node: "@icon".into(),
+                path: "icons/broken.svg".into(),
+                reason: "is not an svg file".into()
+            })
+        );
+    }
+
+    #[test]
+    fn used_icons_reset() {
+        let plugin = IconPlugin::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/icons"));
+        let mut builder = HtmlEmitter::builder();
+        builder.minify().add_plugin(plugin);
+        // Fails after using an icon, so its sprite is never emitted
+        emit_as_str(&builder, r#"@icon "star"; @icon "../star""#).unwrap_err();
+        let html = emit_as_str(&builder, r#"@icon "github""#).unwrap();
+        assert!(!html.contains("icon-star"));
+    }
+
+    #[test]
+    fn icons_from_memory() {
+        let mut files = htmeta::MemoryFiles::new();
+        files.insert(
+            "icons/dot.svg",
+            r#"<svg viewBox="0 0 2 2"><circle r="1"/></svg>"#,
+        );
+        let mut builder = HtmlEmitter::builder();
+        builder
+            .minify()
+            .files(files)
+            .add_plugin(IconPlugin::new("icons"));
+        let html = emit_as_str(&builder, r#"@icon "dot""#).unwrap();
+        assert!(
+            html.contains(r#"<symbol id="icon-dot" viewBox="0 0 2 2"><circle r="1"/></symbol>"#)
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::path::{Path, PathBuf};
+
+use htmeta::{
+    kdl::KdlNode, kdl_ext::KdlNodeExt, EmitResult, EmitStatus, Error, FileProvider, IPlugin,
+    PluginContext,
+};
+
+/// Adds an `@img` command that fills in the attributes responsive images need.
+///
+/// The image given by `src` is read from the root directory, through the emitter's
+/// [`FileProvider`], to emit its `width` and `height`,
+/// so the page doesn't shift around while it loads. When `widths` is given, a `srcset` is
+/// generated out of `photo-480w.jpg` style variants next to the original, along with a
+/// `sizes` attribute that defaults to `100vw`. Widths bigger than the original are skipped.
+///
+/// Missing variants are an error, unless the `resize` feature is enabled, in which case they
+/// are created from the original image.
+///
+/// ```kdl
+/// @img src="photo.jpg" widths="480,960,1920" alt="A photo"
+/// ```
+#[derive(Debug, Clone)]
+pub struct ImgPlugin {
+    root: PathBuf,
+}
+
+impl ImgPlugin {
+    /// Returns a new [`Self`] that resolves image paths relative to `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, src: &str) -> PathBuf {
+        self.root.join(src.trim_start_matches('/'))
+    }
+}
+
+/// Returns `src` with a `-{width}w` suffix added to its file name.
+fn variant(src: &str, width: usize) -> String {
+    let name_start = src.rfind('/').map_or(0, |i| i + 1);
+    match src[name_start..].rfind('.') {
+        Some(dot) => {
+            let (stem, ext) = src.split_at(name_start + dot);
+            format!("{stem}-{width}w{ext}")
+        }
+        None => format!("{src}-{width}w"),
+    }
+}
+
+fn parse_widths(widths: &str) -> EmitResult<Vec<usize>> {
+    widths
+        .split(',')
+        .map(|width| {
+            width.trim().parse().map_err(|_| Error::InvalidProperty {
+                node: "@img".into(),
+                property: "widths".into(),
+                expected: "a comma separated list of widths".into(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the contents of the file at `path`, read from `files`.
+fn read(files: &dyn FileProvider, path: &Path) -> EmitResult<Vec<u8>> {
+    files.read(path).map_err(|error| Error::ReadFile {
+        node: "@img".into(),
+        path: path.display().to_string(),
+        error,
+    })
+}
+
+/// Creates the `target` variant of the image at `source`, resized to `width` by `height`.
+/// `missing` is why `target` couldn't be read.
+#[cfg(feature = "resize")]
+fn resize(
+    files: &dyn FileProvider,
+    source: &Path,
+    target: &Path,
+    (width, height): (usize, usize),
+    _missing: std::io::Error,
+) -> EmitResult {
+    let image = image::load_from_memory(&read(files, source)?).map_err(|e| Error::InvalidFile {
+        node: "@img".into(),
+        path: source.display().to_string(),
+        reason: format!("could not be opened: {e}"),
+    })?;
+    image
+        .resize_exact(
+            width as u32,
+            height as u32,
+            image::imageops::FilterType::Lanczos3,
+        )
+        .save(target)
+        .map_err(|e| Error::WriteFile {
+            node: "@img".into(),
+            path: target.display().to_string(),
+            error: std::io::Error::other(e),
+        })
+}
+
+#[cfg(not(feature = "resize"))]
+fn resize(
+    _files: &dyn FileProvider,
+    _source: &Path,
+    target: &Path,
+    _size: (usize, usize),
+    missing: std::io::Error,
+) -> EmitResult {
+    Err(Error::ReadFile {
+        node: "@img".into(),
+        path: target.display().to_string(),
+        error: std::io::Error::new(
+            missing.kind(),
+            format!("{missing}. Create it or enable resizing"),
+        ),
+    })
+}
+
+impl ImgPlugin {
+    /// Returns the `srcset` for `src`, creating missing variants if needed.
+    fn srcset(
+        &self,
+        files: &dyn FileProvider,
+        src: &str,
+        widths: &[usize],
+        size: imagesize::ImageSize,
+    ) -> EmitResult<String> {
+        let mut candidates = Vec::new();
+        for &width in widths.iter().filter(|&&width| width < size.width) {
+            let url = variant(src, width);
+            let path = self.path(&url);
+            if let Err(missing) = files.read(&path) {
+                let height = (size.height * width + size.width / 2) / size.width;
+                resize(files, &self.path(src), &path, (width, height), missing)?;
+            }
+            candidates.push(format!("{url} {width}w"));
+        }
+        // The original is the biggest candidate
+        candidates.push(format!("{src} {}w", size.width));
+        Ok(candidates.join(", "))
+    }
+}
+
+impl IPlugin for ImgPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        if node.command_name() != Some("img") {
+            return Ok(EmitStatus::Skip);
+        }
+        let vars = &context.emitter.vars;
+        let src = node.get("src").ok_or_else(|| Error::MissingProperty {
+            node: "@img".into(),
+            property: "src".into(),
+        })?;
+        let src = vars.expand_value(src);
+        let path = self.path(&src);
+        let files = context.emitter.files();
+        let size = imagesize::blob_size(&read(files, &path)?).map_err(|e| Error::InvalidFile {
+            node: "@img".into(),
+            path: path.display().to_string(),
+            reason: format!("is not a supported image: {e}"),
+        })?;
+
+        let mut attributes = vec![
+            ("src".to_string(), src.to_string()),
+            ("width".to_string(), size.width.to_string()),
+            ("height".to_string(), size.height.to_string()),
+        ];
+        if let Some(widths) = node.get("widths") {
+            let widths = parse_widths(&vars.expand_value(widths))?;
+            attributes.push(("srcset".into(), self.srcset(files, &src, &widths, size)?));
+            attributes.push(("sizes".into(), "100vw".into()));
+        }
+        for (key, value) in node.keyed_entries() {
+            let value = vars.expand_value(value).into_owned();
+            match attributes.iter_mut().find(|(name, _)| name == key) {
+                // `src` is probed, so it can't be overridden
+                Some((name, _)) if name == "src" => {}
+                Some((_, old)) => *old = value,
+                None if key == "widths" => {}
+                None => attributes.push((key.into(), value)),
+            }
+        }
+
+        let writer = context.writer;
+        write!(writer, "{}<img", context.indent)?;
+        for (key, value) in attributes {
+            write!(
+                writer,
+                r#" {key}="{}""#,
+                html_escape::encode_double_quoted_attribute(&value)
+            )?;
+        }
+        write!(writer, ">")?;
+        context.emitter.write_line(writer)?;
+        Ok(EmitStatus::Emmited)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use htmeta::emit_as_str;
+    use htmeta::{HtmlEmitter, HtmlEmitterBuilder};
+    use htmeta_auto_test::*;
+
+    fn builder() -> HtmlEmitterBuilder {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(ImgPlugin::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures"
+        )));
+        builder
+    }
+
+    auto_html_test!(img_test, builder());
+    auto_html_test_fail!(fail_missing_src, builder());
+
+    #[test]
+    fn img_from_memory() {
+        // The smallest valid GIF, 3 by 2 pixels
+        let gif = b"GIF89a\x03\x00\x02\x00\x00\x00\x00;";
+        let mut files = htmeta::MemoryFiles::new();
+        files.insert("site/dot.gif", gif.as_slice());
+        let mut builder = HtmlEmitter::builder();
+        builder
+            .minify()
+            .files(files)
+            .add_plugin(ImgPlugin::new("site"));
+        htmeta_auto_test::assert_eq!(
+            emit_as_str(&builder, r#"@img src="/dot.gif" alt="Dot""#).unwrap(),
+            r#"<img src="/dot.gif" width="3" height="2" alt="Dot">"#
+        );
+        #[cfg(not(feature = "resize"))]
+        htmeta_auto_test::assert_eq!(
+            emit_as_str(&builder, r#"@img src="/dot.gif" widths="2""#),
+            Err(Error::ReadFile {
+                node: "@img".into(),
+                path: "site/dot-2w.gif".into(),
+                error: std::io::ErrorKind::NotFound.into()
+            })
+        );
+    }
+}
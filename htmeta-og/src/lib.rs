@@ -0,0 +1,102 @@
+use htmeta::{
+    kdl::KdlNode, kdl_ext::KdlNodeExt, EmitResult, EmitStatus, Error, IPlugin, PluginContext,
+};
+
+/// Adds an `@og` command, which emits the Open Graph and Twitter card `meta` tags used by
+/// social media to preview links to a page.
+///
+/// ```kdl
+/// @og title="My trip" description="Pictures from my trip." image="https://example.com/trip.png"
+/// ```
+///
+/// Besides `title`, `description` and `image`, `url`, `type` (which defaults to `website`),
+/// `site` (the site's name) and `twitter` (the site's `@handle`) are supported. `title`,
+/// `description`, `image` and `url` default to the variables of the same name, so pages
+/// that already set `$title` don't have to repeat it.
+#[derive(Debug, Clone, Default)]
+pub struct OgPlugin;
+
+/// Properties that default to the variable of the same name.
+const FROM_VARS: &[&str] = &["title", "description", "image", "url"];
+
+impl OgPlugin {
+    fn emit_og(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        let emitter = context.emitter;
+        let value = |key: &str| {
+            let value = match node.get(key) {
+                Some(value) => emitter.vars.expand_value(value).into_owned(),
+                None if FROM_VARS.contains(&key) => emitter.vars.get(key)?.to_string(),
+                None => return None,
+            };
+            Some(value).filter(|value| !value.is_empty())
+        };
+        let title = value("title").ok_or_else(|| Error::MissingProperty {
+            node: "@og".into(),
+            property: "title".into(),
+        })?;
+        let description = value("description");
+        let image = value("image");
+        let card = match image {
+            Some(_) => "summary_large_image",
+            None => "summary",
+        };
+
+        let tags = [
+            (
+                "property",
+                "og:type",
+                value("type").or(Some("website".into())),
+            ),
+            ("property", "og:title", Some(title.clone())),
+            ("property", "og:description", description.clone()),
+            ("property", "og:image", image.clone()),
+            ("property", "og:url", value("url")),
+            ("property", "og:site_name", value("site")),
+            ("name", "twitter:card", Some(card.into())),
+            ("name", "twitter:site", value("twitter")),
+            ("name", "twitter:title", Some(title)),
+            ("name", "twitter:description", description),
+            ("name", "twitter:image", image),
+        ];
+        for (key, name, content) in tags {
+            let Some(content) = content else {
+                continue;
+            };
+            write!(
+                context.writer,
+                r#"{}<meta {key}="{name}" content="{}">"#,
+                context.indent,
+                html_escape::encode_double_quoted_attribute(&content)
+            )?;
+            emitter.write_line(context.writer)?;
+        }
+        Ok(EmitStatus::Emmited)
+    }
+}
+
+impl IPlugin for OgPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        if node.command_name() != Some("og") {
+            return Ok(EmitStatus::Skip);
+        }
+        self.emit_og(node, context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use htmeta::emit_as_str;
+    use htmeta::{HtmlEmitter, HtmlEmitterBuilder};
+    use htmeta_auto_test::*;
+
+    fn builder() -> HtmlEmitterBuilder {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(OgPlugin);
+        builder
+    }
+
+    auto_html_test!(og_test, builder());
+    auto_html_test!(og_unrelated_vars, builder());
+    auto_html_test_fail!(fail_missing_title, builder());
+}
@@ -1,10 +1,67 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use htmeta::{kdl::KdlNode, EmitResult, EmitStatus, IPlugin, PluginContext};
+use htmeta::{
+    kdl::{KdlDocument, KdlNode, NodeKey},
+    kdl_ext::KdlNodeExt,
+    EmitInfo, EmitResult, EmitStatus, Error, FileProvider, IPlugin, PluginContext, Vars,
+};
 
+/// Adds reusable templates to `htmeta`.
+///
+/// Templates are declared with `@template name="card" { ... }`, or `@template "card" { ... }`,
+/// and instantiated with `@card`.
+/// Properties given to an instantiation become variables inside the template, and its children
+/// are placed wherever the template has a `@children` node. Parameters listed in the
+/// template's `required` property, like `@template "card" required="title href"`, must be
+/// given to every instantiation. `@children only="li"` and
+/// `@children except="script"` select a subset of them by tag name.
+///
+/// Templates are only visible inside the node they are declared in, after their
+/// declaration, and shadow templates of the same name from outer nodes. Those declared
+/// inside another template only exist while it is being instantiated, and those declared
+/// at the top level stay visible for the rest of the document, including the layouts it
+/// extends. Either way, they are forgotten once the document is emitted.
+///
+/// Two more commands make it easy to write templates that only sometimes add markup:
+/// `@if "$value" { ... }` emits its children only if `$value` is set, and
+/// `@wrap "tag" when="$value" { ... }` wraps its children in a `tag` element only if
+/// `$value` is set. Other properties of `@wrap` become the element's attributes. Values that
+/// are empty or `false` count as unset.
+///
+/// ```kdl
+/// @template name="maybe-link" {
+///     @wrap "a" when="$href" href="$href" {
+///         @children
+///     }
+/// }
+/// @maybe-link href="/about" { - "About" }
+/// @maybe-link { - "Not a link" }
+/// ```
+///
+/// Layouts can also be shared through inheritance. A document with `@extends "base.kdl"`
+/// is emitted as the `base.kdl` file next to it, where each `@blockdefault "name" { ... }`
+/// region is replaced by the children of the document's top-level `@block "name" { ... }`,
+/// or kept as-is if there isn't one. Layouts can extend other layouts, and variables and
+/// templates declared before `@extends` are visible to them.
+///
+/// ```kdl
+/// $title "Home"
+/// @extends "base.kdl"
+/// @block "content" {
+///     h1 "Welcome!"
+/// }
+/// ```
 #[derive(Debug, Default, Clone)]
 pub struct TemplatePlugin {
     templates: HashMap<String, KdlNode>,
+    // The document's `@block`s, shared between every copy of the plugin, as they are
+    // collected before it is emitted.
+    blocks: Rc<RefCell<HashMap<String, KdlNode>>>,
 }
 
 impl TemplatePlugin {
@@ -14,58 +71,333 @@ impl TemplatePlugin {
         node: &KdlNode,
         context: PluginContext,
     ) -> EmitResult<EmitStatus> {
-        if node.children().is_some() {
-            return Err(format!(
-                "{name}: Template instantiations must not have bodies!"
-            ))?;
-        }
         let mut subemitter = context.emitter.clone();
 
         let templates = &self.templates;
         let Some(template) = templates.get(name) else {
             return Ok(EmitStatus::Skip);
         };
-        subemitter
-            .vars
-            .extend(node.entries().iter().filter_map(|entry| {
-                Some((
-                    entry.name()?.value(),
-                    context.emitter.vars.expand_value(entry.value()),
-                ))
-            }));
-        subemitter.emit(
+        let required = name_filter(template, "required", "a string of parameter names")?;
+        if let Some(param) = required
+            .unwrap_or_default()
+            .into_iter()
+            .find(|param| node.get(*param).is_none())
+        {
+            return Err(Error::MissingParam {
+                template: name.into(),
+                param: param.into(),
+            });
+        }
+        for (key, value) in node.keyed_entries() {
+            let expanded = context.emitter.vars.expand_value(value);
+            subemitter.vars.insert_from(key, value, expanded);
+        }
+        if context.emitter.warns_unused() {
+            warn_unused_params(name, template, node, &context);
+        }
+        let body = expand_children(
             template
                 .children()
                 .expect("Internal error: template tags must have children"),
-            context.writer,
+            node.children(),
         )?;
+        subemitter.emit_nodes(body.nodes(), context.writer)?;
         Ok(EmitStatus::Emmited)
     }
+
+    /// Emits the layout an `@extends "base.kdl"` node points to, filled with the
+    /// document's blocks.
+    fn emit_extends(&self, node: &KdlNode, context: PluginContext) -> EmitResult {
+        let dir = context
+            .emitter
+            .filename()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""));
+        let path = dir.join(string_argument(node, "path")?);
+        let mut blocks = self.blocks.borrow().clone();
+        let layout = resolve_layout(&path, context.emitter.files(), &mut blocks, &mut Vec::new())?;
+        context
+            .emitter
+            .clone()
+            .emit_nodes(layout.nodes(), context.writer)
+    }
+}
+
+/// Returns `node`'s first argument, which must be a string.
+fn string_argument<'a>(node: &'a KdlNode, argument: &str) -> EmitResult<&'a str> {
+    let name = node.name().value();
+    node.get(0)
+        .ok_or_else(|| Error::MissingArgument {
+            node: name.into(),
+            argument: argument.into(),
+        })?
+        .as_string()
+        .ok_or_else(|| Error::InvalidArgument {
+            node: name.into(),
+            argument: argument.into(),
+            expected: "a string".into(),
+        })
+}
+
+/// Adds the top-level `@block`s of `document` to `blocks`, unless they are already there.
+fn collect_blocks(document: &KdlDocument, blocks: &mut HashMap<String, KdlNode>) -> EmitResult {
+    for node in document.nodes() {
+        if node.name().value() == "@block" {
+            let name = string_argument(node, "name")?;
+            blocks.entry(name.into()).or_insert_with(|| node.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the layout at `path`, along with the layouts it extends, with its
+/// `@blockdefault` regions filled by `blocks`. Blocks of the layout itself only fill the
+/// regions `blocks` doesn't, so those of the documents extending it win.
+///
+/// `visited` holds the layouts being resolved, to catch layouts that extend themselves.
+fn resolve_layout(
+    path: &Path,
+    files: &dyn FileProvider,
+    blocks: &mut HashMap<String, KdlNode>,
+    visited: &mut Vec<PathBuf>,
+) -> EmitResult<KdlDocument> {
+    if visited.iter().any(|visited| visited == path) {
+        return Err(Error::CyclicExtends {
+            path: path.display().to_string(),
+        });
+    }
+    visited.push(path.into());
+    let contents = files
+        .read_to_string(path)
+        .map_err(|error| Error::ReadFile {
+            node: "@extends".into(),
+            path: path.display().to_string(),
+            error,
+        })?;
+    let layout = htmeta::parse_document(&contents).map_err(|e| Error::InvalidFile {
+        node: "@extends".into(),
+        path: path.display().to_string(),
+        reason: format!("is not a valid document: {e}"),
+    })?;
+    collect_blocks(&layout, blocks)?;
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut resolved = KdlDocument::new();
+    for node in layout.nodes() {
+        match node.name().value() {
+            "@block" => {}
+            "@extends" => {
+                let base = dir.join(string_argument(node, "path")?);
+                let base = resolve_layout(&base, files, blocks, visited)?;
+                resolved.nodes_mut().extend(base.nodes().iter().cloned());
+            }
+            _ => resolved.nodes_mut().push(node.clone()),
+        }
+    }
+    fill_blocks(&resolved, blocks)
+}
+
+/// Replaces every `@blockdefault "name"` node inside `body` with the children of the
+/// `@block "name"` in `blocks`, or with its own children if there isn't one.
+fn fill_blocks(body: &KdlDocument, blocks: &HashMap<String, KdlNode>) -> EmitResult<KdlDocument> {
+    let mut filled = KdlDocument::new();
+    for node in body.nodes() {
+        if node.name().value() == "@blockdefault" {
+            let name = string_argument(node, "name")?;
+            let content = blocks.get(name).unwrap_or(node).children();
+            if let Some(content) = content {
+                filled.nodes_mut().extend(content.nodes().iter().cloned());
+            }
+            continue;
+        }
+        let mut node = node.clone();
+        if let Some(children) = node.children() {
+            node.set_children(fill_blocks(children, blocks)?);
+        }
+        filled.nodes_mut().push(node);
+    }
+    Ok(filled)
+}
+
+/// Emits `node`'s children, if any, without any element around them.
+fn emit_children(node: &KdlNode, context: PluginContext) -> EmitResult {
+    if let Some(children) = node.children() {
+        context
+            .emitter
+            .clone()
+            .emit_nodes(children.nodes(), context.writer)?;
+    }
+    Ok(())
+}
+
+/// Returns whether `node`'s `key` entry is set to something other than an empty string or
+/// `false`.
+fn is_set(node: &KdlNode, key: impl Into<NodeKey>, context: &PluginContext) -> EmitResult<bool> {
+    let Some(value) = node.get(key) else {
+        return Ok(false);
+    };
+    let value = context
+        .emitter
+        .vars
+        .expand_known(node.name().value(), value)?;
+    Ok(!value.is_empty() && value != "false")
+}
+
+/// Emits an `@if "$value"` node.
+fn emit_if(node: &KdlNode, context: PluginContext) -> EmitResult {
+    if node.get(0).is_none() {
+        return Err(Error::MissingArgument {
+            node: "@if".into(),
+            argument: "value".into(),
+        });
+    }
+    if is_set(node, 0, &context)? {
+        emit_children(node, context)?;
+    }
+    Ok(())
+}
+
+/// Emits a `@wrap "tag" when="$value"` node.
+fn emit_wrap(node: &KdlNode, context: PluginContext) -> EmitResult {
+    let tag = string_argument(node, "tag")?;
+    if !is_set(node, "when", &context)? {
+        return emit_children(node, context);
+    }
+    let mut wrapper = KdlNode::new(tag);
+    wrapper.entries_mut().extend(
+        node.entries()
+            .iter()
+            .filter(|entry| entry.name().is_some_and(|key| key.value() != "when"))
+            .cloned(),
+    );
+    // A wrapper without content still needs a closing tag
+    wrapper.set_children(node.children().cloned().unwrap_or_default());
+    context
+        .emitter
+        .emit_tag(&wrapper, tag, context.indent, context.writer)
+}
+
+/// Warns about parameters given to the `name` template that its body never references.
+fn warn_unused_params(name: &str, template: &KdlNode, node: &KdlNode, context: &PluginContext) {
+    let body = template
+        .children()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    for (key, _) in node.keyed_entries() {
+        let var = format!("${key}");
+        let is_used = body.match_indices(&var).any(|(i, _)| {
+            // `$title` shouldn't count as a use of `$tit`
+            !body[i + var.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        });
+        if !is_used {
+            context.emitter.warn(format!(
+                "@{name}: Parameter `{key}` is never used by the template."
+            ));
+        }
+    }
+}
+
+/// Returns the names listed in `node`'s `key` property, separated by whitespace. `expected`
+/// describes them, for when the property isn't a string.
+fn name_filter<'a>(
+    node: &'a KdlNode,
+    key: &str,
+    expected: &str,
+) -> EmitResult<Option<Vec<&'a str>>> {
+    let Some(value) = node.get(key) else {
+        return Ok(None);
+    };
+    let names = value.as_string().ok_or_else(|| Error::InvalidProperty {
+        node: node.name().value().into(),
+        property: key.into(),
+        expected: expected.into(),
+    })?;
+    Ok(Some(names.split_whitespace().collect()))
+}
+
+/// Returns the nodes in `children` selected by the `@children` node `slot`.
+fn select_children(slot: &KdlNode, children: Option<&KdlDocument>) -> EmitResult<Vec<KdlNode>> {
+    let only = name_filter(slot, "only", "a string of tag names")?;
+    let except = name_filter(slot, "except", "a string of tag names")?.unwrap_or_default();
+    let Some(children) = children else {
+        return Ok(Vec::new());
+    };
+    Ok(children
+        .nodes()
+        .iter()
+        .filter(|child| {
+            let name = child.name().value();
+            only.as_ref().is_none_or(|only| only.contains(&name)) && !except.contains(&name)
+        })
+        .cloned()
+        .collect())
+}
+
+/// Replaces every `@children` node inside `body` with the instantiation's `children`.
+fn expand_children(body: &KdlDocument, children: Option<&KdlDocument>) -> EmitResult<KdlDocument> {
+    let mut expanded = KdlDocument::new();
+    for node in body.nodes() {
+        match node.name().value() {
+            "@children" => expanded
+                .nodes_mut()
+                .extend(select_children(node, children)?),
+            // Nested templates get their own children
+            "@template" => expanded.nodes_mut().push(node.clone()),
+            _ => {
+                let mut node = node.clone();
+                if let Some(grandchildren) = node.children() {
+                    node.set_children(expand_children(grandchildren, children)?);
+                }
+                expanded.nodes_mut().push(node);
+            }
+        }
+    }
+    Ok(expanded)
 }
 
 impl IPlugin for TemplatePlugin {
     fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
-        let name = node.name().value();
-        let Some(name) = name.strip_prefix('@') else {
+        let Some(name) = node.command_name() else {
             return Ok(EmitStatus::Skip);
         };
-        // Template registry command
-        if name == "template" {
-            Ok(EmitStatus::NeedsMutation)
-        } else {
-            self.emit_template(name, node, context)
+        match name {
+            // Template registry command
+            "template" => Ok(EmitStatus::NeedsMutation),
+            "if" => emit_if(node, context).map(|_| EmitStatus::Emmited),
+            "wrap" => emit_wrap(node, context).map(|_| EmitStatus::Emmited),
+            "extends" => self
+                .emit_extends(node, context)
+                .map(|_| EmitStatus::Emmited),
+            // Blocks are collected ahead of time
+            "block" => Ok(EmitStatus::Emmited),
+            // Emitted outside of `@extends`, like when emitting a layout on its own
+            "blockdefault" => emit_children(node, context).map(|_| EmitStatus::Emmited),
+            _ => self.emit_template(name, node, context),
         }
     }
     fn emit_node_mut(&mut self, node: &KdlNode, context: PluginContext) -> EmitResult<()> {
         let name = node.name().value();
-        let Some(name) = name.strip_prefix('@') else {
+        if !name.starts_with('@') {
             return Err(format!("Unexpected tag in `emit_node_mut`: {name}"))?;
-        };
-        let template_name = node
-            .get("name")
-            .ok_or_else(|| format!("{name}: Template tags must have a `name` parameter!"))?;
+        }
+        let template_name =
+            node.get("name")
+                .or_else(|| node.get(0))
+                .ok_or_else(|| Error::MissingProperty {
+                    node: name.into(),
+                    property: "name".into(),
+                })?;
         if node.children().is_none() {
-            return Err(format!("{name}: Template tags must have children!"))?;
+            return Err(Error::MissingChildren { node: name.into() });
+        }
+        if let Some((key, _)) = node
+            .keyed_entries()
+            .find(|(key, _)| !["name", "required"].contains(key))
+        {
+            return Err(Error::UnknownProperty {
+                node: name.into(),
+                property: key.into(),
+            });
         }
         self.templates.insert(
             context
@@ -77,6 +409,12 @@ impl IPlugin for TemplatePlugin {
         );
         Ok(())
     }
+
+    fn on_start(&self, _: &mut Vars, info: &EmitInfo) -> EmitResult {
+        let mut blocks = self.blocks.borrow_mut();
+        blocks.clear();
+        collect_blocks(info.document, &mut blocks)
+    }
 }
 
 #[cfg(test)]
@@ -95,4 +433,83 @@ mod tests {
     auto_html_test!(basic_test, builder());
     auto_html_test!(param_test, builder());
     auto_html_test!(param_compose_test, builder());
+    auto_html_test!(children_test, builder());
+    auto_html_test!(wrapper_test, builder());
+    auto_html_test!(scoped_test, builder());
+    auto_html_test_fail!(fail_out_of_scope, builder());
+    auto_html_test_fail!(fail_template_without_name, builder());
+    auto_html_test_fail!(fail_missing_param, builder());
+    auto_html_test_fail!(fail_unknown_template_property, builder());
+    auto_html_test!(extends_test, layouts_builder());
+    auto_html_test_fail!(fail_extends_itself, layouts_builder());
+
+    fn layouts_builder() -> HtmlEmitterBuilder {
+        let mut files = htmeta::MemoryFiles::new();
+        files
+            .insert(
+                "layouts/base.kdl",
+                include_str!("../tests/fixtures/layouts/base.kdl"),
+            )
+            .insert(
+                "layouts/post.kdl",
+                include_str!("../tests/fixtures/layouts/post.kdl"),
+            )
+            .insert("layouts/loop.kdl", r#"@extends "loop.kdl""#);
+        let mut builder = builder();
+        builder.files(files);
+        builder
+    }
+
+    #[test]
+    fn missing_layout() {
+        let doc: htmeta::kdl::KdlDocument = r#"@extends "layouts/gone.kdl""#.parse().unwrap();
+        let mut emitter = layouts_builder().build();
+        htmeta_auto_test::assert_eq!(
+            emitter.emit(&doc, &mut Vec::<u8>::new()),
+            Err(Error::ReadFile {
+                node: "@extends".into(),
+                path: "layouts/gone.kdl".into(),
+                error: std::io::ErrorKind::NotFound.into()
+            })
+        );
+    }
+
+    #[test]
+    fn forgotten_after_emit() {
+        let first: htmeta::kdl::KdlDocument = r#"@template "greeting" { p "Hi" }"#.parse().unwrap();
+        let second: htmeta::kdl::KdlDocument = "@greeting".parse().unwrap();
+        let mut emitter = builder().build();
+        emitter.emit(&first, &mut Vec::<u8>::new()).unwrap();
+        htmeta_auto_test::assert_eq!(
+            emitter.emit(&second, &mut Vec::<u8>::new()),
+            Err(Error::UnknownCommand {
+                name: "@greeting".into()
+            })
+        );
+    }
+
+    #[test]
+    fn unused_params() {
+        let doc: htmeta::kdl::KdlDocument = r#"
+            @template name="card" {
+                h2 "$title"
+            }
+            @card title="Hello" titel="Typo"
+        "#
+        .parse()
+        .unwrap();
+        let mut builder = builder();
+        builder.warn_unused();
+        let mut emitter = builder.build();
+        emitter.emit(&doc, &mut Vec::<u8>::new()).unwrap();
+        let warnings: Vec<_> = emitter
+            .take_warnings()
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect();
+        htmeta_auto_test::assert_eq!(
+            warnings,
+            vec!["@card: Parameter `titel` is never used by the template.".to_string()]
+        );
+    }
 }
@@ -0,0 +1,329 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    iter::Peekable,
+    rc::Rc,
+    slice::Iter,
+};
+
+use htmeta::{
+    kdl::{KdlDocument, KdlEntry, KdlNode, KdlValue},
+    kdl_ext::KdlNodeExt,
+    EmitInfo, EmitResult, EmitStatus, IPlugin, PluginContext, Vars,
+};
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+#[derive(Debug, Clone)]
+struct Heading {
+    level: usize,
+    text: String,
+    id: String,
+}
+
+/// Hands out unique `id`s, in the order they are asked for.
+#[derive(Debug, Default)]
+struct Slugger {
+    seen: HashSet<String>,
+}
+
+impl Slugger {
+    /// Returns a unique `id` made out of `text`.
+    fn slug(&mut self, text: &str) -> String {
+        let mut slug = String::new();
+        for c in text.chars().flat_map(char::to_lowercase) {
+            if c.is_alphanumeric() {
+                slug.push(c);
+            } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        let slug = match slug.trim_matches('-') {
+            "" => "section",
+            slug => slug,
+        };
+        let mut id = slug.to_string();
+        let mut count = 0;
+        while self.seen.contains(&id) {
+            count += 1;
+            id = format!("{slug}-{count}");
+        }
+        self.register(&id);
+        id
+    }
+
+    /// Reserves an `id` given by the author.
+    fn register(&mut self, id: &str) {
+        self.seen.insert(id.into());
+    }
+
+    /// Returns `node`'s `id`, creating one if it doesn't have it.
+    fn id(&mut self, node: &KdlNode, vars: &Vars) -> String {
+        match node.get("id") {
+            Some(id) => {
+                let id = vars.expand_value(id).into_owned();
+                self.register(&id);
+                id
+            }
+            None => self.slug(&text(node, vars)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TocState {
+    /// Every heading in the document, collected before emitting it.
+    headings: Vec<Heading>,
+    /// The `id`s of [`Self::headings`], by the address of their node, so emitting them
+    /// gives them the same `id`s. Each one is taken once it is used.
+    ids: HashMap<*const KdlNode, String>,
+    /// Has every `id` of [`Self::headings`], and gives unique ones to headings that
+    /// weren't collected, like those emitted by templates or emitted more than once.
+    slugger: Slugger,
+}
+
+impl TocState {
+    /// Returns the `id` of the heading `node`, which is the collected one the first time
+    /// it is emitted.
+    fn id(&mut self, node: &KdlNode, vars: &Vars) -> String {
+        match self.ids.remove(&(node as *const KdlNode)) {
+            Some(id) => id,
+            None => self.slugger.id(node, vars),
+        }
+    }
+}
+
+/// Gives headings an `id` based on their text and adds a `@toc` command that emits a
+/// table of contents out of them.
+///
+/// Headings that already have an `id` keep it. Optionally, a `#` link to each heading can
+/// be appended to it, so readers can easily share links to sections.
+///
+/// ```kdl
+/// @toc min=2 class="toc"
+/// h2 "Getting started"
+/// h3 "Installation"
+/// ```
+///
+/// `min` and `max` choose which heading levels are listed, and other properties are
+/// added to the list itself.
+///
+/// Headings are collected before the document is emitted, skipping `@when` and `@unless`
+/// blocks that won't be emitted and template definitions. Headings created by other
+/// plugins, like templates, are not listed, and headings inside template-only commands,
+/// like `@if`, always are. Headings that aren't listed still get unique `id`s, which
+/// never take those of listed headings.
+#[derive(Debug, Clone, Default)]
+pub struct TocPlugin {
+    anchors: bool,
+    // Shared between every copy of the plugin, as headings are emitted by nested emitters.
+    state: Rc<RefCell<TocState>>,
+}
+
+impl TocPlugin {
+    /// Appends an `<a class="anchor" href="#id">#</a>` link to each heading.
+    pub fn with_anchors(mut self) -> Self {
+        self.anchors = true;
+        self
+    }
+
+    fn emit_heading(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        let vars = &context.emitter.vars;
+        let id = self.state.borrow_mut().id(node, vars);
+        if node.get("id").is_some() && !self.anchors {
+            return Ok(EmitStatus::Skip);
+        }
+
+        let mut heading = node.clone();
+        if heading.get("id").is_none() {
+            heading.entries_mut().insert(0, prop("id", id.as_str()));
+        }
+        if self.anchors {
+            // Inline text has to become a text node to sit next to the anchor
+            if heading.inline_text().is_some() {
+                let content = heading.entries_mut().pop().unwrap();
+                heading
+                    .ensure_children()
+                    .nodes_mut()
+                    .push(text_node(content));
+            }
+            let mut anchor = KdlNode::new("a");
+            anchor.push(prop("class", "anchor"));
+            anchor.push(prop("href", format!("#{id}")));
+            anchor.push("#");
+            heading.ensure_children().nodes_mut().push(anchor);
+        }
+        context.emitter.emit_tag(
+            &heading,
+            heading.name().value(),
+            context.indent,
+            context.writer,
+        )?;
+        Ok(EmitStatus::Emmited)
+    }
+
+    fn emit_toc(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        let vars = &context.emitter.vars;
+        let level = |key, default| {
+            node.get(key)
+                .and_then(|level| vars.expand_value(level).parse().ok())
+                .unwrap_or(default)
+        };
+        let (min, max) = (level("min", 1), level("max", 6));
+
+        let state = self.state.borrow();
+        let headings: Vec<_> = state
+            .headings
+            .iter()
+            .filter(|heading| (min..=max).contains(&heading.level))
+            .cloned()
+            .collect();
+        let mut list = KdlNode::new("ul");
+        for (key, value) in node.keyed_entries() {
+            if key != "min" && key != "max" {
+                list.push(prop(key, value.clone()));
+            }
+        }
+        list.set_children(toc_items(&mut headings.iter().peekable(), min));
+        context
+            .emitter
+            .emit_tag(&list, "ul", context.indent, context.writer)?;
+        Ok(EmitStatus::Emmited)
+    }
+}
+
+/// Returns a new `key=value` property, formatted to be emitted as an attribute.
+fn prop(key: &str, value: impl Into<KdlValue>) -> KdlEntry {
+    let mut entry = KdlEntry::new_prop(key, value);
+    entry.autoformat();
+    entry
+}
+
+/// Returns a text node with `content`.
+fn text_node(content: KdlEntry) -> KdlNode {
+    let mut node = KdlNode::new("-");
+    node.push(content);
+    node
+}
+
+/// Returns the text content of `node`, including its children's.
+fn text(node: &KdlNode, vars: &Vars) -> String {
+    let mut text = String::new();
+    if let Some(content) = node.inline_text() {
+        text.push_str(&vars.expand_value(content));
+    }
+    for child in node.children().map(KdlDocument::nodes).unwrap_or_default() {
+        text.push_str(&self::text(child, vars));
+    }
+    text
+}
+
+/// Returns a `li` for each heading of at least `level`, with deeper ones nested inside.
+fn toc_items(headings: &mut Peekable<Iter<Heading>>, level: usize) -> KdlDocument {
+    let mut items = KdlDocument::new();
+    while let Some(heading) = headings.next_if(|heading| heading.level >= level) {
+        let mut link = KdlNode::new("a");
+        link.push(prop("href", format!("#{}", heading.id)));
+        link.push(heading.text.as_str());
+        let mut item = KdlNode::new("li");
+        item.ensure_children().nodes_mut().push(link);
+
+        let nested = toc_items(headings, heading.level + 1);
+        if !nested.nodes().is_empty() {
+            let mut list = KdlNode::new("ul");
+            list.set_children(nested);
+            item.ensure_children().nodes_mut().push(list);
+        }
+        items.nodes_mut().push(item);
+    }
+    items
+}
+
+/// Collects every heading inside `nodes` that will be emitted into `state`, in order.
+fn collect_headings(nodes: &[KdlNode], vars: &mut Vars, state: &mut TocState, info: &EmitInfo) {
+    for node in nodes {
+        let name = node.name().value();
+        if is_skipped(node, vars, info) {
+            continue;
+        } else if let Some(var) = name.strip_prefix('$')
+            && let Some(value) = node.get(0)
+        {
+            let value = vars.expand_value(value).into_owned();
+            vars.insert(var, value.into());
+        } else if let Some(level) = HEADINGS.iter().position(|heading| *heading == name) {
+            let id = state.slugger.id(node, vars);
+            state.ids.insert(node, id.clone());
+            state.headings.push(Heading {
+                level: level + 1,
+                text: text(node, vars),
+                id,
+            });
+        } else if let Some(children) = node.children() {
+            collect_headings(children.nodes(), vars, state, info);
+        }
+    }
+}
+
+/// Returns whether `node` and its children won't be emitted where they are: `@when` and
+/// `@unless` blocks whose flag says so, template definitions and raw blocks.
+fn is_skipped(node: &KdlNode, vars: &Vars, info: &EmitInfo) -> bool {
+    let name = node.name().value();
+    match (name, node.get(0)) {
+        ("@when" | "@unless", Some(flag)) => {
+            info.is_defined(&vars.expand_value(flag)) == (name == "@unless")
+        }
+        ("@template" | "_", _) => true,
+        _ => false,
+    }
+}
+
+impl IPlugin for TocPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        match node.name().value() {
+            "@toc" => self.emit_toc(node, context),
+            name if HEADINGS.contains(&name) => self.emit_heading(node, context),
+            _ => Ok(EmitStatus::Skip),
+        }
+    }
+
+    fn on_start(&self, vars: &mut Vars, info: &EmitInfo) -> EmitResult {
+        let mut vars = vars.clone();
+        let mut state = TocState::default();
+        collect_headings(info.document.nodes(), &mut vars, &mut state, info);
+        *self.state.borrow_mut() = state;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use htmeta::emit_as_str;
+    use htmeta::{HtmlEmitter, HtmlEmitterBuilder};
+    use htmeta_auto_test::*;
+
+    fn builder() -> HtmlEmitterBuilder {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(TocPlugin::default());
+        builder
+    }
+
+    fn with_anchors() -> HtmlEmitterBuilder {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(TocPlugin::default().with_anchors());
+        builder
+    }
+
+    auto_html_test!(toc_test, builder());
+    auto_html_test!(anchors_test, with_anchors());
+    auto_html_test!(skipped_headings, builder());
+
+    fn with_templates() -> HtmlEmitterBuilder {
+        let mut builder = HtmlEmitter::builder();
+        builder.add_plugin(htmeta_template::TemplatePlugin::default());
+        builder.add_plugin(TocPlugin::default());
+        builder
+    }
+
+    auto_html_test!(template_headings, with_templates());
+}
@@ -0,0 +1,278 @@
+//! Helpers to use `htmeta` documents as templates in web applications.
+//!
+//! The main type is [`HtmetaTemplate`], which renders a document with some variables.
+//! With the `axum` or `actix` features enabled, it can be returned directly from handlers:
+//!
+//! ```rust,ignore
+//! async fn index() -> HtmetaTemplate {
+//!     HtmetaTemplate::new("templates/index.kdl").var("title", "Home")
+//! }
+//! ```
+//!
+//! Variables are escaped wherever they would end up in the page as-is, so they can hold
+//! user input. Use [`HtmetaTemplate::trusted_var`] for variables holding `HTML`.
+//!
+//! Documents are parsed only once and then cached for the lifetime of the process. Use
+//! [`clear_cache`] if you need to pick up changes, e.g. while developing.
+//!
+//! Templates that fail to render in a handler become a `500 Internal Server Error`. Use
+//! [`set_error_handler`] to find out why.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, OnceLock},
+};
+
+use htmeta::{kdl::KdlDocument, HtmlEmitter, HtmlEmitterBuilder};
+
+static DOCUMENTS: LazyLock<Mutex<HashMap<PathBuf, Arc<KdlDocument>>>> =
+    LazyLock::new(Default::default);
+
+static BUILDER: OnceLock<fn() -> HtmlEmitterBuilder> = OnceLock::new();
+
+static ERROR_HANDLER: OnceLock<fn(&Path, &Error)> = OnceLock::new();
+
+/// Sets the function used to create the [`HtmlEmitterBuilder`] that renders every
+/// [`HtmetaTemplate`]. Useful for registering plugins or changing indentation.
+///
+/// Can only be called once, before rendering any template. Returns `false` if a
+/// function was already set.
+pub fn set_builder(builder: fn() -> HtmlEmitterBuilder) -> bool {
+    BUILDER.set(builder).is_ok()
+}
+
+/// Sets the function called with the document's path and the error when a
+/// [`HtmetaTemplate`] returned from a handler fails to render, e.g. to log it. The
+/// response is a `500 Internal Server Error` either way.
+///
+/// Can only be called once. Returns `false` if a function was already set.
+pub fn set_error_handler(handler: fn(&Path, &Error)) -> bool {
+    ERROR_HANDLER.set(handler).is_ok()
+}
+
+/// The default builder: minified output, with templates enabled if the `templates`
+/// feature is on.
+fn default_builder() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.minify();
+    #[cfg(feature = "templates")]
+    builder.add_plugin(htmeta_template::TemplatePlugin::default());
+    builder
+}
+
+/// Forgets all parsed documents, so they are read again next time they are rendered.
+pub fn clear_cache() {
+    DOCUMENTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Returns the parsed document at `path`, reading and caching it if needed.
+fn load(path: &Path) -> Result<Arc<KdlDocument>, Error> {
+    let mut documents = DOCUMENTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(doc) = documents.get(path) {
+        return Ok(doc.clone());
+    }
+    let doc: Arc<KdlDocument> = Arc::new(htmeta::parse_document(&std::fs::read_to_string(path)?)?);
+    documents.insert(path.to_owned(), doc.clone());
+    Ok(doc)
+}
+
+/// Errors that can happen while rendering a [`HtmetaTemplate`].
+#[derive(Debug)]
+pub enum Error {
+    /// The document could not be read.
+    Io(std::io::Error),
+    /// The document is not valid `KDL`.
+    Parse(htmeta::kdl::KdlError),
+    /// The document could not be emitted.
+    Emit(htmeta::Error),
+}
+
+use Error::*;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Io(io) => Display::fmt(io, f),
+            Parse(parse) => Display::fmt(parse, f),
+            Emit(emit) => Display::fmt(emit, f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Io(io) => Some(io),
+            Parse(parse) => Some(parse),
+            Emit(emit) => Some(emit),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Io(value)
+    }
+}
+
+impl From<htmeta::kdl::KdlError> for Error {
+    fn from(value: htmeta::kdl::KdlError) -> Self {
+        Parse(value)
+    }
+}
+
+impl From<htmeta::Error> for Error {
+    fn from(value: htmeta::Error) -> Self {
+        Emit(value)
+    }
+}
+
+/// A document to be rendered with a set of variables.
+///
+/// # Example
+/// ```rust,no_run
+/// use htmeta_web::HtmetaTemplate;
+///
+/// let html = HtmetaTemplate::new("templates/greeting.kdl")
+///     .var("name", "World")
+///     .render()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HtmetaTemplate {
+    /// Path to the `htmeta` document.
+    pub path: PathBuf,
+    /// Variables available to the document, as if they were defined at its top. Their
+    /// values are escaped.
+    pub vars: HashMap<String, String>,
+    /// Like [`Self::vars`], but their values are written as-is, so they can hold `HTML`.
+    pub trusted_vars: HashMap<String, String>,
+}
+
+impl HtmetaTemplate {
+    /// Returns a new [`Self`] for the document at `path`, without any variables.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            vars: HashMap::new(),
+            trusted_vars: HashMap::new(),
+        }
+    }
+
+    /// Adds a variable named `name` to this template. Its value is escaped, so it is safe
+    /// to use with user input.
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Adds a variable named `name` whose value is written into the page as-is. Only use
+    /// it for `HTML` you trust, never for user input.
+    pub fn trusted_var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.trusted_vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Renders the document into a [`String`].
+    pub fn render(&self) -> Result<String, Error> {
+        let doc = load(&self.path)?;
+        let mut emitter = BUILDER.get_or_init(|| default_builder)().build();
+        emitter.vars.extend(
+            self.trusted_vars
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str().into())),
+        );
+        for (name, value) in &self.vars {
+            emitter.vars.insert_untrusted(name, value.as_str().into());
+        }
+        let mut buf = Vec::<u8>::new();
+        emitter.emit(&doc, &mut buf)?;
+        Ok(String::from_utf8(buf).map_err(htmeta::Error::from)?)
+    }
+
+    /// Passes `error` to the function set with [`set_error_handler`], if any.
+    #[cfg(any(feature = "axum", feature = "actix"))]
+    fn report(&self, error: &Error) {
+        if let Some(handler) = ERROR_HANDLER.get() {
+            handler(&self.path, error)
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::response::IntoResponse for HtmetaTemplate {
+    fn into_response(self) -> axum::response::Response {
+        match self.render() {
+            Ok(html) => axum::response::Html(html).into_response(),
+            Err(e) => {
+                self.report(&e);
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
+impl actix_web::Responder for HtmetaTemplate {
+    type Body = actix_web::body::BoxBody;
+
+    fn respond_to(self, _req: &actix_web::HttpRequest) -> actix_web::HttpResponse<Self::Body> {
+        use actix_web::{http::header::ContentType, HttpResponse};
+        match self.render() {
+            Ok(html) => HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body(html),
+            Err(e) => {
+                self.report(&e);
+                HttpResponse::InternalServerError().finish()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn render_with_vars() {
+        let template = HtmetaTemplate::new(fixture("greeting.kdl"));
+        let first = template.clone().var("name", "World").render().unwrap();
+        let second = template.var("name", "htmeta").render().unwrap();
+        assert_eq!(first, "<main><h1>Hello, World!</h1></main>");
+        assert_eq!(second, "<main><h1>Hello, htmeta!</h1></main>");
+    }
+
+    #[test]
+    fn render_escapes_vars() {
+        let template = HtmetaTemplate::new(fixture("greeting.kdl"));
+        let escaped = template
+            .clone()
+            .var("name", "<script>alert(1)</script>")
+            .render()
+            .unwrap();
+        let trusted = template
+            .trusted_var("name", "<em>you</em>")
+            .render()
+            .unwrap();
+        assert_eq!(
+            escaped,
+            "<main><h1>Hello, &lt;script&gt;alert(1)&lt;/script&gt;!</h1></main>"
+        );
+        assert_eq!(trusted, "<main><h1>Hello, <em>you</em>!</h1></main>");
+    }
+
+    #[test]
+    fn render_missing_file() {
+        let result = HtmetaTemplate::new(fixture("missing.kdl")).render();
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+}
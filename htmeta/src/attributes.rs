@@ -0,0 +1,103 @@
+//! Handling of attributes that are set more than once in the same node, or whose names
+//! aren't valid.
+
+use std::collections::HashMap;
+
+use kdl::{KdlEntry, KdlValue};
+
+use crate::{EmitResult, Error};
+
+/// What to do when a node sets the same attribute more than once, which would otherwise
+/// produce invalid `HTML`. This can easily happen by accident once templates pass
+/// attributes around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateAttributes {
+    /// Fails with [`Error::DuplicateAttribute`].
+    Error,
+    /// Keeps the first value, like browsers do.
+    KeepFirst,
+    /// Keeps the last value, like `KDL` itself does. This is the default.
+    #[default]
+    KeepLast,
+    /// Joins every `class` value with spaces and every `style` value with `;`. Other
+    /// attributes keep their last value.
+    Merge,
+}
+
+impl DuplicateAttributes {
+    /// Removes the duplicate attributes in `entries` according to `self`. Attributes stay
+    /// where they were first set. `tag` is only used for errors.
+    pub(crate) fn apply(self, entries: Vec<KdlEntry>, tag: &str) -> EmitResult<Vec<KdlEntry>> {
+        let mut result: Vec<KdlEntry> = Vec::with_capacity(entries.len());
+        let mut positions = HashMap::new();
+        for entry in entries {
+            let Some(key) = entry.name().map(|key| key.value().to_string()) else {
+                result.push(entry);
+                continue;
+            };
+            let Some(&i) = positions.get(&key) else {
+                positions.insert(key, result.len());
+                result.push(entry);
+                continue;
+            };
+            match self {
+                Self::Error => {
+                    return Err(Error::DuplicateAttribute {
+                        tag: tag.into(),
+                        attribute: key,
+                    })
+                }
+                Self::KeepFirst => (),
+                Self::Merge if key == "class" || key == "style" => {
+                    result[i] = merge(&result[i], &entry, &key);
+                }
+                Self::KeepLast | Self::Merge => result[i] = entry,
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Returns a single `key` attribute with the values of both `first` and `second`.
+fn merge(first: &KdlEntry, second: &KdlEntry, key: &str) -> KdlEntry {
+    let text = |entry: &KdlEntry| match entry.value() {
+        KdlValue::String(value) => value.clone(),
+        value => value.to_string(),
+    };
+    let (a, b) = (text(first), text(second));
+    let value = match key {
+        "style" => format!("{}; {b}", a.trim_end().trim_end_matches(';')),
+        _ => format!("{a} {b}"),
+    };
+    let mut merged = KdlEntry::new_prop(key, value);
+    merged.autoformat();
+    if let (Some(format), Some(original)) = (merged.format_mut(), first.format()) {
+        format.leading = original.leading.clone();
+    }
+    merged
+}
+
+/// What to do with attribute names that aren't valid `HTML`, which usually come from a
+/// variable. Written as-is, a name like `onmouseover=alert(1) x` would sneak in attributes
+/// the author never meant to add.
+///
+/// Names are invalid if they are empty or have whitespace, control characters, quotes,
+/// `>`, `/` or `=` in them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidAttributes {
+    /// Fails with [`Error::InvalidAttributeName`]. This is the default.
+    #[default]
+    Error,
+    /// Leaves the attribute out, with a warning.
+    Skip,
+    /// Writes the name as-is. Only useful if something else produces the final `HTML`.
+    Allow,
+}
+
+/// Returns `true` if `name` can be written as an attribute name.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(|c: char| {
+            c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '>' | '/' | '=')
+        })
+}
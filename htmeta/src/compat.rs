@@ -0,0 +1,156 @@
+//! Compatibility with documents written for `KDL` 1.0.
+
+use std::{iter::Peekable, str::Chars};
+
+use kdl::{KdlDocument, KdlError};
+
+/// Parses `input` as a `KDL` 2.0 document. If that fails, `input` is translated from
+/// `KDL` 1.0 with [`translate_v1`] and parsed again, so documents written before the
+/// upgrade keep working.
+///
+/// If neither works, the error from the `KDL` 2.0 parser is returned.
+///
+/// # Example
+/// ```rust
+/// let doc = htmeta::parse_document(r#"input disabled=true value=r"C:\path""#).unwrap();
+/// assert_eq!(doc.to_string().trim(), r#"input disabled=#true value="C:\\path""#);
+/// ```
+pub fn parse_document(input: &str) -> Result<KdlDocument, KdlError> {
+    input
+        .parse()
+        .or_else(|err| translate_v1(input).parse().map_err(|_| err))
+}
+
+/// Translates a `KDL` 1.0 document into `KDL` 2.0 syntax.
+///
+/// Bare `true`, `false` and `null` become `#true`, `#false` and `#null`, while strings,
+/// including raw strings like `r#"..."#`, are rewritten as escaped single-line strings.
+/// Everything else, comments included, is copied as-is.
+pub fn translate_v1(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    // Whether the last character copied can be part of an identifier
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                output.push(c);
+                copy_while(&mut chars, &mut output, |c| c != '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                output.push(c);
+                copy_block_comment(&mut chars, &mut output);
+            }
+            '"' => push_string(&mut output, &escaped_string(&mut chars)),
+            'r' if !in_word && matches!(chars.peek(), Some('"' | '#')) => {
+                match raw_string(&mut chars) {
+                    Some(value) => push_string(&mut output, &value),
+                    None => output.push(c),
+                }
+            }
+            c if is_identifier_char(c) && !in_word => {
+                let mut word = c.to_string();
+                copy_while(&mut chars, &mut word, is_identifier_char);
+                if matches!(word.as_str(), "true" | "false" | "null") {
+                    output.push('#');
+                }
+                output.push_str(&word);
+            }
+            c => output.push(c),
+        }
+        in_word = output.chars().next_back().is_some_and(is_identifier_char);
+    }
+    output
+}
+
+fn is_identifier_char(c: char) -> bool {
+    !c.is_whitespace() && !"\\/(){}<>;[]=,\"#".contains(c)
+}
+
+fn copy_while(chars: &mut Peekable<Chars>, output: &mut String, f: impl Fn(char) -> bool) {
+    while let Some(&c) = chars.peek()
+        && f(c)
+    {
+        output.push(c);
+        chars.next();
+    }
+}
+
+/// Copies a `/* */` comment, which can be nested, right after its `/`.
+fn copy_block_comment(chars: &mut Peekable<Chars>, output: &mut String) {
+    output.extend(chars.next());
+    let mut depth = 1;
+    while let Some(c) = chars.next() {
+        output.push(c);
+        match (c, chars.peek()) {
+            ('*', Some('/')) => depth -= 1,
+            ('/', Some('*')) => depth += 1,
+            _ => continue,
+        }
+        output.extend(chars.next());
+        if depth == 0 {
+            return;
+        }
+    }
+}
+
+/// Reads the value of a `KDL` 1.0 string, right after its opening quote.
+fn escaped_string(chars: &mut Peekable<Chars>) -> String {
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('r') => value.push('\r'),
+                Some('t') => value.push('\t'),
+                Some('b') => value.push('\u{8}'),
+                Some('f') => value.push('\u{c}'),
+                Some('u') if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    let mut hex = String::new();
+                    copy_while(chars, &mut hex, |c| c != '}');
+                    chars.next();
+                    value.extend(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32));
+                }
+                // `\"`, `\\` and `\/`
+                Some(c) => value.push(c),
+                None => value.push('\\'),
+            },
+            c => value.push(c),
+        }
+    }
+    value
+}
+
+/// Reads the value of a `KDL` 1.0 raw string, right after its `r`. Returns `None` if it
+/// isn't actually a raw string, in which case nothing is consumed.
+fn raw_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    let rest: String = chars.clone().collect();
+    let hashes = rest.len() - rest.trim_start_matches('#').len();
+    let body = rest[hashes..].strip_prefix('"')?;
+    let terminator = format!("\"{}", "#".repeat(hashes));
+    let end = body.find(&terminator).unwrap_or(body.len());
+    let value = body[..end].to_string();
+    // Skips the hashes, the quotes and the value itself.
+    let consumed = hashes + 1 + value.chars().count() + terminator.len();
+    chars.nth(consumed - 1);
+    Some(value)
+}
+
+/// Writes `value` as a `KDL` 2.0 string.
+fn push_string(output: &mut String, value: &str) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => output.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
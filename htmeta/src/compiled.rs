@@ -0,0 +1,131 @@
+//! Pre-rendered documents that can be emitted many times with different variables.
+use std::borrow::Cow;
+
+use kdl::KdlDocument;
+use serde::{Deserialize, Serialize};
+
+use crate::{EmitResult, HtmlEmitterBuilder, Vars, Writer};
+
+/// Marks the start of a variable that couldn't be expanded while compiling.
+pub(crate) const DEFERRED_START: char = '\u{1}';
+/// Same as [`DEFERRED_START`], but the variable's value must be escaped once known.
+pub(crate) const DEFERRED_ESCAPED_START: char = '\u{2}';
+/// Marks the end of a deferred variable.
+pub(crate) const DEFERRED_END: char = '\u{3}';
+
+/// Marks every deferred variable inside `text` as needing escaping.
+pub(crate) fn mark_escaped(text: Cow<'_, str>) -> Cow<'_, str> {
+    if text.contains(DEFERRED_START) {
+        text.replace(DEFERRED_START, &DEFERRED_ESCAPED_START.to_string())
+            .into()
+    } else {
+        text
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Segment {
+    /// Already rendered `HTML`.
+    Static(String),
+    /// A variable expression, like `$price|fixed:2`, to be expanded when emitting.
+    Var { expr: String, escape: bool },
+}
+
+/// A document whose static parts are already rendered, leaving only variables that weren't
+/// defined while compiling to be expanded later.
+///
+/// It can be (de)serialized with [`serde`], so you can compile documents once, store them
+/// and quickly emit them with different variables.
+///
+/// # Example
+/// ```rust
+/// use htmeta::{CompiledDoc, HtmlEmitter, Vars};
+/// use kdl::KdlDocument;
+///
+/// let doc: KdlDocument = r#"h1 "Hello, $name!""#.parse().unwrap();
+/// let compiled = CompiledDoc::compile(HtmlEmitter::builder().minify(), &doc).unwrap();
+///
+/// let mut vars = Vars::default();
+/// vars.insert("name", "World".into());
+/// let mut result = Vec::<u8>::new();
+/// compiled.emit(&vars, &mut result).unwrap();
+/// assert_eq!(result, b"<h1>Hello, World!</h1>");
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompiledDoc {
+    segments: Vec<Segment>,
+    /// Whether escaped variables keep their character entity references, like the emitter
+    /// it was compiled with.
+    #[serde(default)]
+    preserve_entities: bool,
+}
+
+impl CompiledDoc {
+    /// Compiles `document` using an emitter created by `builder`.
+    ///
+    /// Variables defined inside the document are expanded right away, as are plugins and
+    /// build flags. Only variables missing from the document are left for [`Self::emit`].
+    ///
+    /// As their values aren't known yet, those variables can't decide what gets emitted:
+    /// using them in conditions, like a `@when` flag, or in links that are normalized fails
+    /// with [`Error::DeferredVariable`](crate::Error::DeferredVariable).
+    pub fn compile(builder: &HtmlEmitterBuilder, document: &KdlDocument) -> EmitResult<Self> {
+        let mut emitter = builder.build();
+        emitter.vars.defer_missing = true;
+        let mut buf = Vec::<u8>::new();
+        emitter.emit(document, &mut buf)?;
+        let output = String::from_utf8(buf)?;
+        Ok(Self::split(&output, builder.preserve_entities))
+    }
+
+    fn split(mut output: &str, preserve_entities: bool) -> Self {
+        let mut segments = Vec::new();
+        while let Some(start) = output.find([DEFERRED_START, DEFERRED_ESCAPED_START]) {
+            let Some(len) = output[start..].find(DEFERRED_END) else {
+                break;
+            };
+            if start > 0 {
+                segments.push(Segment::Static(output[..start].into()));
+            }
+            let marker = &output[start..start + len];
+            let escape = marker.starts_with(DEFERRED_ESCAPED_START);
+            // Markers are one byte long
+            segments.push(Segment::Var {
+                expr: marker[1..].into(),
+                escape,
+            });
+            output = &output[start + len + 1..];
+        }
+        if !output.is_empty() {
+            segments.push(Segment::Static(output.into()));
+        }
+        Self {
+            segments,
+            preserve_entities,
+        }
+    }
+
+    /// Emits the compiled document into `writer`, expanding the remaining variables with `vars`.
+    pub fn emit(&self, vars: &Vars, writer: Writer) -> EmitResult {
+        // Unescaped variables are written as-is, unless they are untrusted
+        let mut escaping = vars.clone();
+        escaping.escape_untrusted = true;
+        for segment in &self.segments {
+            match segment {
+                Segment::Static(html) => write!(writer, "{}", html)?,
+                // Quotes are escaped too, as the variable may be inside an attribute
+                Segment::Var { expr, escape: true } => write!(
+                    writer,
+                    "{}",
+                    crate::escape_text(&vars.expand_string(expr), self.preserve_entities)
+                        .replace('"', "&quot;")
+                )?,
+                Segment::Var {
+                    expr,
+                    escape: false,
+                } => write!(writer, "{}", escaping.expand_string(expr))?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! Helpers for post-processors that work with a page's stylesheets, like inlining them.
+//!
+//! Parsing is deliberately simple: rules are split by their braces, and selectors are
+//! matched by tag names, classes and `id`s only.
+
+use std::{borrow::Cow, fmt::Display, sync::LazyLock};
+
+use regex::Regex;
+
+static COMMENT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)/\*.*?\*/").unwrap());
+/// Selectors made of a tag name, classes and an `id`, without combinators or pseudo-classes.
+static COMPOUND_SELECTOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([a-zA-Z][\w-]*|\*)?((?:[.#]-?[_a-zA-Z][\w-]*)*)$").unwrap());
+
+/// Opening tags in `HTML`. The first group is the tag's name, and the second its
+/// attributes, which [`ATTRIBUTE`] finds.
+pub static TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<([a-zA-Z][\w-]*)([^>]*)>").unwrap());
+/// Attributes with a quoted value inside an opening tag. The first group is the attribute's
+/// name, and the second its value.
+pub static ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?:^|\s)([\w-]+)="([^"]*)""#).unwrap());
+
+/// Returns `css` without its `/* comments */`.
+pub fn strip_comments(css: &str) -> Cow<'_, str> {
+    COMMENT.replace_all(css, "")
+}
+
+/// A top-level rule of a stylesheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule<'a> {
+    /// The rule's selectors, or the at-rule's name and condition, like `@media print`.
+    pub prelude: &'a str,
+    /// What is inside the rule's braces, including the nested rules of an at-rule. At-rules
+    /// without braces, like `@import`, have none.
+    pub body: Option<&'a str>,
+}
+
+impl Rule<'_> {
+    /// Returns whether this is an at-rule, like `@media` or `@import`.
+    pub fn is_at_rule(&self) -> bool {
+        self.prelude.starts_with('@')
+    }
+}
+
+impl Display for Rule<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.body {
+            Some(body) => write!(f, "{}{{{}}}", self.prelude, body.trim()),
+            None => write!(f, "{};", self.prelude),
+        }
+    }
+}
+
+/// Splits `css` into its top-level rules. Their preludes are trimmed.
+pub fn rules(css: &str) -> Vec<Rule<'_>> {
+    let mut rules = Vec::new();
+    let (mut start, mut body_start, mut depth) = (0, 0, 0);
+    for (i, c) in css.char_indices() {
+        match c {
+            '{' => {
+                if depth == 0 {
+                    body_start = i;
+                }
+                depth += 1;
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    rules.push(Rule {
+                        prelude: css[start..body_start].trim(),
+                        body: Some(&css[body_start + 1..i]),
+                    });
+                    start = i + 1;
+                }
+            }
+            // At-rules without a body, like `@import`
+            ';' if depth == 0 => {
+                let prelude = css[start..i].trim();
+                if !prelude.is_empty() {
+                    rules.push(Rule {
+                        prelude,
+                        body: None,
+                    });
+                }
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    rules
+}
+
+/// An element of a page, as far as [`Selector`]s can tell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element<'a> {
+    /// The element's tag name, lowercased.
+    pub tag: String,
+    pub classes: Vec<&'a str>,
+    pub id: Option<&'a str>,
+}
+
+impl<'a> Element<'a> {
+    /// Returns the element of the tag named `tag`, with `attributes` as matched by [`TAG`].
+    pub fn parse(tag: &str, attributes: &'a str) -> Self {
+        let mut element = Self {
+            tag: tag.to_lowercase(),
+            classes: Vec::new(),
+            id: None,
+        };
+        for attribute in ATTRIBUTE.captures_iter(attributes) {
+            let value = attribute.get(2).unwrap().as_str();
+            match &attribute[1] {
+                "class" => element.classes.extend(value.split_whitespace()),
+                "id" => element.id = Some(value),
+                _ => (),
+            }
+        }
+        element
+    }
+}
+
+/// Returns every element in `html`, in order.
+pub fn elements(html: &str) -> Vec<Element<'_>> {
+    TAG.captures_iter(html)
+        .map(|tag| Element::parse(tag.get(1).unwrap().as_str(), tag.get(2).unwrap().as_str()))
+        .collect()
+}
+
+/// A compound selector, like `p.note#intro`. Tag names are case-insensitive, but classes
+/// and `id`s aren't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    /// The tag name, lowercased, or `None` for any tag.
+    pub tag: Option<String>,
+    pub classes: Vec<String>,
+    pub id: Option<String>,
+}
+
+impl Selector {
+    /// Parses a compound selector. Returns `None` for anything else, like selectors with
+    /// combinators, attribute selectors or pseudo-classes.
+    pub fn parse(selector: &str) -> Option<Self> {
+        let captures = COMPOUND_SELECTOR.captures(selector.trim())?;
+        let mut parsed = Selector {
+            tag: captures
+                .get(1)
+                .map(|tag| tag.as_str().to_lowercase())
+                .filter(|tag| tag != "*"),
+            classes: Vec::new(),
+            id: None,
+        };
+        let mut rest = &captures[2];
+        while let Some(kind) = rest.chars().next() {
+            let end = rest[1..].find(['.', '#']).map_or(rest.len(), |end| end + 1);
+            match kind {
+                '.' => parsed.classes.push(rest[1..end].into()),
+                _ => parsed.id = Some(rest[1..end].into()),
+            }
+            rest = &rest[end..];
+        }
+        Some(parsed)
+    }
+
+    /// Returns whether the selector applies to `element`.
+    pub fn matches(&self, element: &Element) -> bool {
+        self.tag.as_ref().is_none_or(|tag| *tag == element.tag)
+            && self
+                .classes
+                .iter()
+                .all(|class| element.classes.contains(&class.as_str()))
+            && self
+                .id
+                .as_ref()
+                .is_none_or(|id| Some(id.as_str()) == element.id)
+    }
+
+    /// Returns how specific the selector is, so the most specific rules win.
+    pub fn specificity(&self) -> (usize, usize, usize) {
+        (
+            self.id.iter().count(),
+            self.classes.len(),
+            self.tag.iter().count(),
+        )
+    }
+}
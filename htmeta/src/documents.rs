@@ -0,0 +1,68 @@
+//! Files that define several documents at once, through `@document` nodes.
+
+use std::path::{Component, Path};
+
+use kdl::{KdlDocument, KdlNode};
+
+use crate::{EmitResult, Error};
+
+/// Returns the documents defined by the top-level `@document "path" { ... }` nodes of
+/// `file`, along with their paths, or an empty list if it has none.
+///
+/// Every other top-level node, like variables and templates, is shared by the documents:
+/// it is placed before each document's own nodes.
+///
+/// Paths must be relative and can't contain `..`, so documents can't be written outside
+/// of the directory they are written to.
+///
+/// # Example
+/// ```rust
+/// let file = htmeta::parse_document(r#"
+///     $site "Blog"
+///     @document "404.html" { h1 "$site: Not found" }
+///     @document "500.html" { h1 "$site: Oops" }
+/// "#).unwrap();
+/// let documents = htmeta::split_documents(&file).unwrap();
+/// assert_eq!(documents[0].0, "404.html");
+/// assert_eq!(documents[1].1.nodes().len(), 2);
+/// ```
+pub fn split_documents(file: &KdlDocument) -> EmitResult<Vec<(String, KdlDocument)>> {
+    let is_document = |node: &&KdlNode| node.name().value() == "@document";
+    let shared: Vec<_> = file
+        .nodes()
+        .iter()
+        .filter(|node| !is_document(node))
+        .cloned()
+        .collect();
+    file.nodes()
+        .iter()
+        .filter(is_document)
+        .map(|node| {
+            let path = node
+                .get(0)
+                .and_then(|path| path.as_string())
+                .ok_or_else(|| Error::MissingArgument {
+                    node: "@document".into(),
+                    argument: "path".into(),
+                })?;
+            let is_inside = Path::new(path)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+            if !is_inside {
+                return Err(Error::InvalidArgument {
+                    node: "@document".into(),
+                    argument: "path".into(),
+                    expected: "a relative path without `..`".into(),
+                });
+            }
+            let mut document = KdlDocument::new();
+            document.nodes_mut().extend(shared.iter().cloned());
+            if let Some(children) = node.children() {
+                document
+                    .nodes_mut()
+                    .extend(children.nodes().iter().cloned());
+            }
+            Ok((path.to_string(), document))
+        })
+        .collect()
+}
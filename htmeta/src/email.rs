@@ -0,0 +1,183 @@
+//! Making pages fit for email clients, for [`OutputMode::Email`].
+
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+
+use crate::{
+    css::{self, Element, Selector, ATTRIBUTE, TAG},
+    EmitResult, PostProcessor,
+};
+
+static SCRIPT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)[ \t]*<script\b[^>]*>.*?</script>\n?").unwrap());
+static STYLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)[ \t]*<style\b[^>]*>(.*?)</style>\n?").unwrap());
+static CLOSING_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"</([a-zA-Z][\w-]*)>").unwrap());
+
+/// Tags that email clients don't know about, which become `div`s.
+const UNSUPPORTED_TAGS: &[&str] = &[
+    "article", "aside", "footer", "header", "main", "nav", "section",
+];
+/// Attributes that stop tables used for layout from getting borders and spacing.
+const TABLE_ATTRIBUTES: &[(&str, &str)] = &[
+    ("role", "presentation"),
+    ("border", "0"),
+    ("cellpadding", "0"),
+    ("cellspacing", "0"),
+];
+
+#[derive(Debug)]
+struct Rule {
+    selector: Selector,
+    declarations: Vec<String>,
+}
+
+/// Where the emitted pages are shown, which changes how they are written. Check out
+/// [`HtmlEmitterBuilder::mode`](crate::HtmlEmitterBuilder::mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Web browsers. Pages are written as they are. This is the default.
+    #[default]
+    Web,
+    /// Email clients. Pages go through [`Email`] once emitted.
+    Email,
+}
+
+/// Changes pages so email clients show them as intended: the rules of their `<style>`s
+/// are inlined into `style` attributes, scripts are removed, tags that email clients
+/// don't support become `div`s and tables are marked as layout.
+///
+/// Rules that can't be inlined, like ones with combinators, pseudo-classes or at-rules
+/// like `@media` and `@import`, are kept in a `<style>` in the page's `head`.
+#[derive(Debug, Default)]
+pub struct Email;
+
+/// Splits `css` into the rules that can be inlined and the text of the ones that can't.
+fn parse_css(css: &str) -> (Vec<Rule>, String) {
+    let css = css::strip_comments(css);
+    let mut inlined = Vec::new();
+    let mut kept = String::new();
+    for rule in css::rules(&css) {
+        let Some(body) = rule.body.filter(|_| !rule.is_at_rule()) else {
+            kept.push_str(&rule.to_string());
+            continue;
+        };
+        let declarations: Vec<String> = body
+            .split(';')
+            .map(str::trim)
+            .filter(|declaration| !declaration.is_empty())
+            .map(String::from)
+            .collect();
+        for selector in rule.prelude.split(',') {
+            match Selector::parse(selector) {
+                Some(selector) => inlined.push(Rule {
+                    selector,
+                    declarations: declarations.clone(),
+                }),
+                None => kept.push_str(&format!("{}{{{}}}", selector.trim(), body.trim())),
+            }
+        }
+    }
+    (inlined, kept)
+}
+
+/// Returns the `style` that `rules` give to a tag, followed by its own `style`.
+fn inline_style(rules: &[Rule], tag: &str, attributes: &str) -> Option<String> {
+    let element = Element::parse(tag, attributes);
+    let own_style = ATTRIBUTE
+        .captures_iter(attributes)
+        .find(|attribute| &attribute[1] == "style")
+        .map(|attribute| attribute.get(2).unwrap().as_str());
+    let mut matched: Vec<_> = rules
+        .iter()
+        .filter(|rule| rule.selector.matches(&element))
+        .collect();
+    // Stable, so rules written later still win among equally specific ones
+    matched.sort_by_key(|rule| rule.selector.specificity());
+
+    let mut declarations: Vec<(String, String)> = Vec::new();
+    for declaration in matched.iter().flat_map(|rule| &rule.declarations) {
+        let property = declaration
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        declarations.retain(|(existing, _)| *existing != property);
+        let escaped = declaration.replace('&', "&amp;").replace('"', "&quot;");
+        declarations.push((property, escaped));
+    }
+    let mut style: Vec<_> = declarations
+        .into_iter()
+        .map(|(_, declaration)| declaration)
+        .collect();
+    style.extend(own_style.map(|style| style.trim_end_matches(';').trim().to_string()));
+    (!style.is_empty()).then(|| style.join("; "))
+}
+
+/// Returns the opening tag changed for email clients.
+fn email_tag(rules: &[Rule], tag: &Captures) -> String {
+    let name = tag[1].to_lowercase();
+    let style = inline_style(rules, &name, &tag[2]);
+    let name = match UNSUPPORTED_TAGS.contains(&name.as_str()) {
+        true => "div".to_string(),
+        false => name,
+    };
+
+    // Attributes are written back in order, with `style` replaced
+    let mut attributes = String::new();
+    let mut has_style = false;
+    for attribute in ATTRIBUTE.captures_iter(&tag[2]) {
+        let (key, mut value) = (&attribute[1], &attribute[2]);
+        if key == "style" {
+            has_style = true;
+            value = style.as_deref().unwrap_or_default();
+        }
+        attributes.push_str(&format!(r#" {key}="{value}""#));
+    }
+    if let Some(style) = style.filter(|_| !has_style) {
+        attributes.push_str(&format!(r#" style="{style}""#));
+    }
+    if name == "table" {
+        for (key, value) in TABLE_ATTRIBUTES {
+            if !ATTRIBUTE
+                .captures_iter(&tag[2])
+                .any(|attribute| &attribute[1] == *key)
+            {
+                attributes.push_str(&format!(r#" {key}="{value}""#));
+            }
+        }
+    }
+    // Attributes without a value, like `disabled`
+    let bare = ATTRIBUTE.replace_all(&tag[2], "");
+    if !bare.trim().is_empty() {
+        attributes.push(' ');
+        attributes.push_str(bare.trim());
+    }
+    format!("<{name}{attributes}>")
+}
+
+impl PostProcessor for Email {
+    fn process(&self, html: String) -> EmitResult<String> {
+        let html = SCRIPT.replace_all(&html, "");
+        let mut css = String::new();
+        for style in STYLE.captures_iter(&html) {
+            css.push_str(&style[1]);
+        }
+        let html = STYLE.replace_all(&html, "");
+        let (rules, kept) = parse_css(&css);
+
+        let html = TAG.replace_all(&html, |tag: &Captures| email_tag(&rules, tag));
+        let html = CLOSING_TAG.replace_all(&html, |tag: &Captures| {
+            match UNSUPPORTED_TAGS.contains(&tag[1].to_lowercase().as_str()) {
+                true => "</div>".to_string(),
+                false => tag[0].to_string(),
+            }
+        });
+        if kept.is_empty() {
+            return Ok(html.into_owned());
+        }
+        Ok(html.replacen("</head>", &format!("<style>{kept}</style></head>"), 1))
+    }
+}
@@ -3,22 +3,107 @@ use std::fmt::{Debug, Display};
 
 /// The crate's error type.
 ///
-/// It is currently very primitive, and implements [`From`] for both
+/// Each kind of failure has its own variant, so you can `match` on them to handle
+/// errors programmatically. It also implements [`From`] for both
 /// [io::Error](std::io::Error) and [`String`] to allow for some plugin
 /// custom error reporting.
 ///
 /// # User Message
-/// If your plugin needs to report some user error, you can use [`Error::UserError`]
-/// to show them a message, and hopefully let them know what went wrong.
+/// If your plugin needs to report some user error that doesn't fit any other variant,
+/// you can use [`Error::UserError`] to show them a message, and hopefully let them know
+/// what went wrong.
 ///
 /// Line location is planned in the future to improve diagnostics.
 #[derive(Debug, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum Error {
     #[serde(skip)] // never happens in testing
     /// An error that happened while trying to `emit` code.
     Io(std::io::Error),
     /// User Error with a friendly message to inform what went wrong.
     UserError { message: String },
+    /// A node has both inline text and children.
+    MixedContent { tag: String },
+    /// A void tag, like `br` or `img`, has children.
+    VoidTagWithChildren { tag: String },
+    /// A node is missing a required positional argument.
+    MissingArgument { node: String, argument: String },
+    /// A node is missing a required property.
+    MissingProperty { node: String, property: String },
+    /// A node has a property it doesn't support, usually a misspelled one.
+    UnknownProperty { node: String, property: String },
+    /// A template was instantiated without one of its required parameters.
+    MissingParam { template: String, param: String },
+    /// A node that requires children has none.
+    MissingChildren { node: String },
+    /// A node's property has a value of the wrong kind.
+    InvalidProperty {
+        node: String,
+        property: String,
+        expected: String,
+    },
+    /// A `@command` node wasn't handled by any plugin. Usually a misspelled template.
+    UnknownCommand { name: String },
+    /// The document is nested deeper than the emitter's maximum depth. `path` holds the names
+    /// of the nodes leading to the one that was too deep, starting at the root.
+    TooDeep { max_depth: usize, path: Vec<String> },
+    /// A node sets the same attribute more than once, and the emitter was told to fail on
+    /// it. Check out [`DuplicateAttributes`](crate::DuplicateAttributes).
+    DuplicateAttribute { tag: String, attribute: String },
+    /// A node sets an attribute whose name isn't valid `HTML`. Check out
+    /// [`InvalidAttributes`](crate::InvalidAttributes).
+    InvalidAttributeName { tag: String, attribute: String },
+    /// An `@assert` node's condition was false.
+    AssertionFailed { message: String },
+    /// A variable isn't set, and the emitter was told to fail on it. Check out
+    /// [`HtmlEmitterBuilder::on_missing_var`](crate::HtmlEmitterBuilder::on_missing_var).
+    MissingVariable { name: String },
+    /// A `@while` node's condition was still true after `max` iterations.
+    TooManyIterations { max: usize },
+    /// A node's positional argument has a value of the wrong kind.
+    InvalidArgument {
+        node: String,
+        argument: String,
+        expected: String,
+    },
+    #[serde(skip)] // io errors can't be compared
+    /// A file a node needs, like an icon or a layout, couldn't be read.
+    ReadFile {
+        node: String,
+        path: String,
+        error: std::io::Error,
+    },
+    #[serde(skip)] // io errors can't be compared
+    /// A file a node creates, like a resized image, couldn't be written.
+    WriteFile {
+        node: String,
+        path: String,
+        error: std::io::Error,
+    },
+    /// A file a node needs was read, but its contents aren't what the node expected.
+    InvalidFile {
+        node: String,
+        path: String,
+        reason: String,
+    },
+    /// A layout extends itself, directly or through other layouts.
+    CyclicExtends { path: String },
+    /// A node has a child it doesn't support, like a tag inside a code block.
+    UnexpectedChild { node: String, child: String },
+    #[serde(skip)] // plugin errors can't be compared
+    /// A plugin failed with an error of its own type, while emitting `node`. Match on it by
+    /// downcasting `error`.
+    Plugin {
+        node: String,
+        error: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// While compiling a [`CompiledDoc`](crate::CompiledDoc), a variable that is only set
+    /// when emitting it was used to decide what gets emitted, like in a `@when` flag.
+    DeferredVariable { node: String },
+    #[serde(skip)] // never happens in testing
+    /// The emitted `HTML` wasn't valid UTF-8, which can only happen if a plugin wrote
+    /// invalid bytes. Post-processors need it as text.
+    InvalidUtf8(std::string::FromUtf8Error),
 }
 
 use Error::*;
@@ -26,7 +111,129 @@ use Error::*;
 impl PartialEq for Error {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
+            (Io(_), _) | (_, Io(_)) => false,
             (UserError { message: a }, UserError { message: b }) => a == b,
+            (MixedContent { tag: a }, MixedContent { tag: b }) => a == b,
+            (VoidTagWithChildren { tag: a }, VoidTagWithChildren { tag: b }) => a == b,
+            (
+                MissingArgument { node, argument },
+                MissingArgument {
+                    node: node_b,
+                    argument: argument_b,
+                },
+            ) => node == node_b && argument == argument_b,
+            (
+                MissingProperty { node, property },
+                MissingProperty {
+                    node: node_b,
+                    property: property_b,
+                },
+            ) => node == node_b && property == property_b,
+            (
+                UnknownProperty { node, property },
+                UnknownProperty {
+                    node: node_b,
+                    property: property_b,
+                },
+            ) => node == node_b && property == property_b,
+            (
+                MissingParam { template, param },
+                MissingParam {
+                    template: template_b,
+                    param: param_b,
+                },
+            ) => template == template_b && param == param_b,
+            (MissingChildren { node: a }, MissingChildren { node: b }) => a == b,
+            (
+                InvalidProperty {
+                    node,
+                    property,
+                    expected,
+                },
+                InvalidProperty {
+                    node: node_b,
+                    property: property_b,
+                    expected: expected_b,
+                },
+            ) => node == node_b && property == property_b && expected == expected_b,
+            (UnknownCommand { name: a }, UnknownCommand { name: b }) => a == b,
+            (
+                TooDeep { max_depth, path },
+                TooDeep {
+                    max_depth: max_depth_b,
+                    path: path_b,
+                },
+            ) => max_depth == max_depth_b && path == path_b,
+            (
+                DuplicateAttribute { tag, attribute },
+                DuplicateAttribute {
+                    tag: tag_b,
+                    attribute: attribute_b,
+                },
+            ) => tag == tag_b && attribute == attribute_b,
+            (
+                InvalidAttributeName { tag, attribute },
+                InvalidAttributeName {
+                    tag: tag_b,
+                    attribute: attribute_b,
+                },
+            ) => tag == tag_b && attribute == attribute_b,
+            (AssertionFailed { message: a }, AssertionFailed { message: b }) => a == b,
+            (MissingVariable { name: a }, MissingVariable { name: b }) => a == b,
+            (TooManyIterations { max: a }, TooManyIterations { max: b }) => a == b,
+            (
+                InvalidArgument {
+                    node,
+                    argument,
+                    expected,
+                },
+                InvalidArgument {
+                    node: node_b,
+                    argument: argument_b,
+                    expected: expected_b,
+                },
+            ) => node == node_b && argument == argument_b && expected == expected_b,
+            (
+                ReadFile { node, path, error },
+                ReadFile {
+                    node: node_b,
+                    path: path_b,
+                    error: error_b,
+                },
+            ) => node == node_b && path == path_b && error.kind() == error_b.kind(),
+            (
+                WriteFile { node, path, error },
+                WriteFile {
+                    node: node_b,
+                    path: path_b,
+                    error: error_b,
+                },
+            ) => node == node_b && path == path_b && error.kind() == error_b.kind(),
+            (
+                InvalidFile { node, path, reason },
+                InvalidFile {
+                    node: node_b,
+                    path: path_b,
+                    reason: reason_b,
+                },
+            ) => node == node_b && path == path_b && reason == reason_b,
+            (CyclicExtends { path: a }, CyclicExtends { path: b }) => a == b,
+            (
+                UnexpectedChild { node, child },
+                UnexpectedChild {
+                    node: node_b,
+                    child: child_b,
+                },
+            ) => node == node_b && child == child_b,
+            (
+                Plugin { node, error },
+                Plugin {
+                    node: node_b,
+                    error: error_b,
+                },
+            ) => node == node_b && error.to_string() == error_b.to_string(),
+            (DeferredVariable { node: a }, DeferredVariable { node: b }) => a == b,
+            (InvalidUtf8(a), InvalidUtf8(b)) => a == b,
             _ => false,
         }
     }
@@ -37,6 +244,78 @@ impl std::fmt::Display for Error {
         match self {
             Io(io) => Display::fmt(io, f),
             UserError { message } => write!(f, "{}", message),
+            MixedContent { tag } => write!(
+                f,
+                "{tag}: Nodes with inline text and children aren't allowed."
+            ),
+            VoidTagWithChildren { tag } => write!(f, "{tag}: Void tags can't have children!"),
+            MissingArgument { node, argument } => {
+                write!(f, "{node}: Missing the `{argument}` argument!")
+            }
+            MissingProperty { node, property } => {
+                write!(f, "{node}: Missing the `{property}` property!")
+            }
+            UnknownProperty { node, property } => {
+                write!(f, "{node}: Unknown property `{property}`!")
+            }
+            MissingParam { template, param } => {
+                write!(f, "@{template}: Missing the `{param}` parameter!")
+            }
+            MissingChildren { node } => write!(f, "{node}: This node must have children!"),
+            InvalidProperty {
+                node,
+                property,
+                expected,
+            } => write!(f, "{node}: `{property}` must be {expected}!"),
+            UnknownCommand { name } => write!(f, "{name}: Unknown command or template!"),
+            TooDeep { max_depth, path } => {
+                // Only the innermost nodes are useful to find the culprit
+                if path.len() > 8 {
+                    write!(f, "... > ")?;
+                }
+                write!(
+                    f,
+                    "{}: Nodes can't be nested more than {max_depth} levels deep!",
+                    path[path.len().saturating_sub(8)..].join(" > ")
+                )
+            }
+            DuplicateAttribute { tag, attribute } => {
+                write!(
+                    f,
+                    "{tag}: The `{attribute}` attribute is set more than once!"
+                )
+            }
+            InvalidAttributeName { tag, attribute } => {
+                write!(f, "{tag}: `{attribute}` is not a valid attribute name!")
+            }
+            AssertionFailed { message } => write!(f, "@assert: {message}"),
+            MissingVariable { name } => write!(f, "The variable `${name}` is not set!"),
+            TooManyIterations { max } => {
+                write!(
+                    f,
+                    "@while: The condition is still true after {max} iterations!"
+                )
+            }
+            InvalidArgument {
+                node,
+                argument,
+                expected,
+            } => write!(f, "{node}: The `{argument}` argument must be {expected}!"),
+            ReadFile { node, path, error } => write!(f, "{node}: Could not read {path}: {error}"),
+            WriteFile { node, path, error } => {
+                write!(f, "{node}: Could not write {path}: {error}")
+            }
+            InvalidFile { node, path, reason } => write!(f, "{node}: {path} {reason}!"),
+            CyclicExtends { path } => write!(f, "@extends: {path} extends itself!"),
+            UnexpectedChild { node, child } => {
+                write!(f, "{node}: `{child}` nodes aren't allowed inside!")
+            }
+            Plugin { node, error } => write!(f, "{node}: {error}"),
+            DeferredVariable { node } => write!(
+                f,
+                "{node}: Variables that are only set when emitting a compiled document can't be used here!"
+            ),
+            InvalidUtf8(error) => write!(f, "The emitted HTML is not valid UTF-8: {error}"),
         }
     }
 }
@@ -44,8 +323,10 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Io(io) => Some(io),
-            UserError { .. } => None,
+            Io(io) | ReadFile { error: io, .. } | WriteFile { error: io, .. } => Some(io),
+            InvalidUtf8(error) => Some(error),
+            Plugin { error, .. } => Some(&**error),
+            _ => None,
         }
     }
 }
@@ -56,6 +337,12 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(value: std::string::FromUtf8Error) -> Self {
+        InvalidUtf8(value)
+    }
+}
+
 impl From<String> for Error {
     fn from(value: String) -> Self {
         UserError { message: value }
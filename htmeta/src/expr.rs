@@ -0,0 +1,262 @@
+//! `${ ... }` expressions, which compute values out of variables, like `${ $price * 2 }`.
+//!
+//! Expressions support numbers, `'single'` or `"double"` quoted strings, variables, `+`, `-`,
+//! `*`, `/`, `%` and parentheses. `+` adds numbers and joins anything else.
+//!
+//! Values can be compared with `==`, `!=`, `<`, `<=`, `>` and `>=`, and combined with `&&`,
+//! `||` and `!`. Empty strings, `false` and `0` count as false.
+
+use std::{fmt::Display, iter::Peekable, str::Chars};
+
+use crate::{filters, Vars};
+
+/// Why an expression couldn't be evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ExprError {
+    /// A variable used by the expression isn't set.
+    Missing(String),
+    /// The expression can't be computed, with a message saying why.
+    Invalid(String),
+    /// The text isn't an expression at all, with a message saying why.
+    Syntax(String),
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExprError::Missing(name) => write!(f, "`${name}` is not set."),
+            ExprError::Invalid(message) | ExprError::Syntax(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+type ExprResult<T> = Result<T, ExprError>;
+
+fn invalid<T>(message: impl Into<String>) -> ExprResult<T> {
+    Err(ExprError::Invalid(message.into()))
+}
+
+fn syntax<T>(message: impl Into<String>) -> ExprResult<T> {
+    Err(ExprError::Syntax(message.into()))
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+}
+
+impl Value {
+    /// Returns the value as a number, if it is one or is text that looks like one.
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::Text(text) => text.trim().parse().ok(),
+            Value::Bool(_) => None,
+        }
+    }
+
+    fn text(self) -> String {
+        match self {
+            Value::Number(number) => filters::format_float(number),
+            Value::Text(text) => text,
+            Value::Bool(boolean) => boolean.to_string(),
+        }
+    }
+}
+
+/// Returns whether `text` counts as true in conditions. Empty strings, `false` and `0` don't.
+pub(crate) fn is_truthy(text: &str) -> bool {
+    !matches!(text.trim(), "" | "false" | "0")
+}
+
+/// Returns the length of the expression at the start of `text`, right after its `${`, not
+/// counting the closing `}`. Returns `None` if it is never closed.
+pub(crate) fn len(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('}', None) => return Some(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Evaluates `expr` and returns its value as text.
+pub(crate) fn eval(expr: &str, vars: &Vars) -> ExprResult<String> {
+    let mut parser = Parser {
+        chars: expr.chars().peekable(),
+        vars,
+    };
+    let value = parser.or()?;
+    parser.skip_whitespace();
+    if let Some(c) = parser.chars.next() {
+        return syntax(format!("Unexpected `{c}`."));
+    }
+    Ok(value.text())
+}
+
+struct Parser<'a, 'b> {
+    chars: Peekable<Chars<'a>>,
+    vars: &'a Vars<'b>,
+}
+
+impl Parser<'_, '_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    /// Returns the next operator if it is one of `operators`. Longer operators that start
+    /// like shorter ones must come first.
+    fn operator(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        self.skip_whitespace();
+        let operator = operators
+            .iter()
+            .find(|operator| self.chars.clone().take(operator.len()).eq(operator.chars()))?;
+        self.chars.nth(operator.len() - 1);
+        Some(*operator)
+    }
+
+    /// `and ('||' and)*`
+    fn or(&mut self) -> ExprResult<Value> {
+        let mut value = self.and()?;
+        while self.operator(&["||"]).is_some() {
+            let rhs = self.and()?;
+            value = Value::Bool(is_truthy(&value.text()) || is_truthy(&rhs.text()));
+        }
+        Ok(value)
+    }
+
+    /// `comparison ('&&' comparison)*`
+    fn and(&mut self) -> ExprResult<Value> {
+        let mut value = self.comparison()?;
+        while self.operator(&["&&"]).is_some() {
+            let rhs = self.comparison()?;
+            value = Value::Bool(is_truthy(&value.text()) && is_truthy(&rhs.text()));
+        }
+        Ok(value)
+    }
+
+    /// `sum (('==' | '!=' | '<=' | '>=' | '<' | '>') sum)?`
+    fn comparison(&mut self) -> ExprResult<Value> {
+        let value = self.sum()?;
+        let Some(operator) = self.operator(&["==", "!=", "<=", ">=", "<", ">"]) else {
+            return Ok(value);
+        };
+        let rhs = self.sum()?;
+        // Numbers are compared as numbers, anything else as text
+        let ordering = match (value.number(), rhs.number()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => Some(value.text().cmp(&rhs.text())),
+        };
+        let Some(ordering) = ordering else {
+            return Ok(Value::Bool(false));
+        };
+        Ok(Value::Bool(match operator {
+            "==" => ordering.is_eq(),
+            "!=" => ordering.is_ne(),
+            "<=" => ordering.is_le(),
+            ">=" => ordering.is_ge(),
+            "<" => ordering.is_lt(),
+            _ => ordering.is_gt(),
+        }))
+    }
+
+    /// `product (('+' | '-') product)*`
+    fn sum(&mut self) -> ExprResult<Value> {
+        let mut value = self.product()?;
+        while let Some(operator) = self.operator(&["+", "-"]) {
+            let rhs = self.product()?;
+            value = match (operator, value.number(), rhs.number()) {
+                ("+", Some(a), Some(b)) => Value::Number(a + b),
+                ("+", _, _) => Value::Text(value.text() + &rhs.text()),
+                (_, Some(a), Some(b)) => Value::Number(a - b),
+                _ => return invalid("Only numbers can be subtracted."),
+            };
+        }
+        Ok(value)
+    }
+
+    /// `unary (('*' | '/' | '%') unary)*`
+    fn product(&mut self) -> ExprResult<Value> {
+        let mut value = self.unary()?;
+        while let Some(operator) = self.operator(&["*", "/", "%"]) {
+            let rhs = self.unary()?;
+            let (Some(a), Some(b)) = (value.number(), rhs.number()) else {
+                return invalid(format!("`{operator}` only works with numbers."));
+            };
+            if operator != "*" && b == 0.0 {
+                return invalid("Division by zero.");
+            }
+            value = Value::Number(match operator {
+                "*" => a * b,
+                "/" => a / b,
+                _ => a % b,
+            });
+        }
+        Ok(value)
+    }
+
+    /// `'-' unary | '!' unary | atom`
+    fn unary(&mut self) -> ExprResult<Value> {
+        if self.operator(&["!"]).is_some() {
+            return Ok(Value::Bool(!is_truthy(&self.unary()?.text())));
+        }
+        if self.operator(&["-"]).is_some() {
+            let Some(number) = self.unary()?.number() else {
+                return invalid("Only numbers can be negated.");
+            };
+            return Ok(Value::Number(-number));
+        }
+        self.atom()
+    }
+
+    /// A number, string, variable or parenthesized expression.
+    fn atom(&mut self) -> ExprResult<Value> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some('(') => {
+                let value = self.or()?;
+                match self.operator(&[")"]) {
+                    Some(_) => Ok(value),
+                    None => syntax("Missing a closing `)`."),
+                }
+            }
+            Some(quote @ ('\'' | '"')) => {
+                let text = self.take_while(|c| c != quote);
+                self.chars.next();
+                Ok(Value::Text(text))
+            }
+            Some('$') => {
+                let name = self.take_while(|c| c.is_alphanumeric() || c == '_');
+                match self.vars.get(&name) {
+                    Some(value) => Ok(Value::Text(
+                        self.vars.escape_if_untrusted(&name, value.to_string()),
+                    )),
+                    None => Err(ExprError::Missing(name)),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = c.to_string() + &self.take_while(|c| c.is_ascii_digit() || c == '.');
+                match number.parse() {
+                    Ok(number) => Ok(Value::Number(number)),
+                    Err(_) => syntax(format!("`{number}` is not a valid number.")),
+                }
+            }
+            Some(c) => syntax(format!("Unexpected `{c}`.")),
+            None => syntax("Expected a value."),
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.chars.next_if(|c| f(*c)) {
+            text.push(c);
+        }
+        text
+    }
+}
@@ -0,0 +1,117 @@
+//! Where plugins read files from, so documents can be emitted without touching the disk.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Reads the files plugins depend on, like icons or stylesheets. Check out
+/// [`crate::HtmlEmitterBuilder::files`].
+pub trait FileProvider {
+    /// Returns the contents of the file at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Returns the contents of the file at `path` as text.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Reads files from disk. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFiles;
+
+impl FileProvider for RealFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+}
+
+/// Returns `path` without `.` components, so `./icons/a.svg` and `icons/a.svg` are the
+/// same file.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+/// Files kept in memory, for tests or environments without a filesystem.
+///
+/// # Example
+/// ```rust
+/// use htmeta::{FileProvider, MemoryFiles};
+/// let mut files = MemoryFiles::new();
+/// files.insert("icons/star.svg", "<svg></svg>");
+/// let star = files.read_to_string("./icons/star.svg".as_ref()).unwrap();
+/// assert_eq!(star, "<svg></svg>");
+/// assert!(files.read("icons/moon.svg".as_ref()).is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFiles {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFiles {
+    /// Returns a new [`Self`] without any files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file at `path`, replacing any previous one.
+    pub fn insert(&mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> &mut Self {
+        self.files.insert(normalize(path.as_ref()), contents.into());
+        self
+    }
+}
+
+impl FileProvider for MemoryFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files
+            .get(&normalize(path))
+            .cloned()
+            .ok_or_else(|| not_found(path))
+    }
+}
+
+/// Files embedded into the binary, usually with [`include_bytes`].
+///
+/// # Example
+/// ```rust
+/// use htmeta::{EmbeddedFiles, FileProvider};
+/// static FILES: EmbeddedFiles = EmbeddedFiles::new(&[
+///     ("style.css", b"body { margin: 0 }"),
+///     // ("logo.svg", include_bytes!("../assets/logo.svg")),
+/// ]);
+/// assert_eq!(FILES.read("style.css".as_ref()).unwrap(), b"body { margin: 0 }");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedFiles {
+    files: &'static [(&'static str, &'static [u8])],
+}
+
+impl EmbeddedFiles {
+    /// Returns a new [`Self`] with `files`, given as `(path, contents)` pairs.
+    pub const fn new(files: &'static [(&'static str, &'static [u8])]) -> Self {
+        Self { files }
+    }
+}
+
+impl FileProvider for EmbeddedFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = normalize(path);
+        self.files
+            .iter()
+            .find(|(name, _)| normalize(name.as_ref()) == path)
+            .map(|(_, contents)| contents.to_vec())
+            .ok_or_else(|| not_found(&path))
+    }
+}
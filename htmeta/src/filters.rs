@@ -0,0 +1,68 @@
+//! Variable filters and number formatting.
+//!
+//! Filters are applied to a variable's value by appending them to its name, like
+//! `$price|fixed:2`. They can be chained: `$total|fixed:2|thousands`.
+
+/// Amount of significant digits kept when displaying floats. This is enough to hide
+/// representation noise like `0.30000000000000004` without losing meaningful digits.
+const FLOAT_SIGNIFICANT_DIGITS: usize = 15;
+
+/// Formats a float without representation noise or scientific notation.
+pub(crate) fn format_float(value: f64) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    let rounded: f64 = format!("{:.*e}", FLOAT_SIGNIFICANT_DIGITS - 1, value)
+        .parse()
+        .unwrap_or(value);
+    rounded.to_string()
+}
+
+/// Formats `value` with exactly `digits` decimal places. Returns `None` if `value` isn't a number.
+fn fixed(value: &str, digits: &str) -> Option<String> {
+    let digits: usize = digits.parse().ok()?;
+    let value: f64 = value.trim().parse().ok()?;
+    Some(format!("{:.*}", digits, value))
+}
+
+/// Groups the integer digits of `value` in threes using `separator`.
+/// Returns `None` if `value` isn't a number.
+fn thousands(value: &str, separator: &str) -> Option<String> {
+    let value = value.trim();
+    value.parse::<f64>().ok()?;
+    let (sign, unsigned) = match value.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", value),
+    };
+    let (int, frac) = unsigned.split_at(unsigned.find('.').unwrap_or(unsigned.len()));
+    if !int.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut grouped = String::with_capacity(value.len() + int.len() / 3);
+    for (i, digit) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    Some(format!("{sign}{grouped}{frac}"))
+}
+
+/// Applies the filter called `name` to `value`. Values the filter can't handle are
+/// returned untouched.
+fn apply(value: String, name: &str, arg: Option<&str>) -> String {
+    let result = match name {
+        "fixed" => fixed(&value, arg.unwrap_or("0")),
+        "thousands" => thousands(&value, arg.unwrap_or(",")),
+        _ => None,
+    };
+    result.unwrap_or(value)
+}
+
+/// Applies every filter in `chain`, written like `|fixed:2|thousands`, to `value`.
+pub(crate) fn apply_chain(value: String, chain: &str) -> String {
+    re!(FILTER, r"\|(\w+)(?::(\w+|[^\w\s|]))?");
+    FILTER.captures_iter(chain).fold(value, |value, filter| {
+        apply(value, &filter[1], filter.get(2).map(|arg| arg.as_str()))
+    })
+}
@@ -0,0 +1,54 @@
+//! Shortcuts for reading [`KdlNode`]s, for plugins.
+
+use kdl::{KdlNode, KdlValue};
+
+/// Extension methods for [`KdlNode`] that most plugins end up needing.
+///
+/// # Example
+/// ```rust
+/// use htmeta::kdl::KdlNode;
+/// use htmeta::kdl_ext::KdlNodeExt;
+/// let node: KdlNode = r#"@card "first" title="Hi" "second""#.parse().unwrap();
+/// assert_eq!(node.command_name(), Some("card"));
+/// assert_eq!(node.args().count(), 2);
+/// let keys: Vec<_> = node.keyed_entries().map(|(key, _)| key).collect();
+/// assert_eq!(keys, ["title"]);
+/// ```
+pub trait KdlNodeExt {
+    /// Returns the name of a `@command` node without its `@`, like `card` for `@card`, or
+    /// `None` if it isn't a command.
+    fn command_name(&self) -> Option<&str>;
+    /// Returns the node's positional arguments, in order.
+    fn args(&self) -> impl Iterator<Item = &KdlValue>;
+    /// Returns the node's properties as keys and values, in order.
+    fn keyed_entries(&self) -> impl Iterator<Item = (&str, &KdlValue)>;
+    /// Returns the node's inline text, which is its last entry if it is positional, like
+    /// `"Title"` in `h1 class="big" "Title"`.
+    fn inline_text(&self) -> Option<&KdlValue>;
+}
+
+impl KdlNodeExt for KdlNode {
+    fn command_name(&self) -> Option<&str> {
+        self.name().value().strip_prefix('@')
+    }
+
+    fn args(&self) -> impl Iterator<Item = &KdlValue> {
+        self.entries()
+            .iter()
+            .filter(|entry| entry.name().is_none())
+            .map(|entry| entry.value())
+    }
+
+    fn keyed_entries(&self) -> impl Iterator<Item = (&str, &KdlValue)> {
+        self.entries()
+            .iter()
+            .filter_map(|entry| Some((entry.name()?.value(), entry.value())))
+    }
+
+    fn inline_text(&self) -> Option<&KdlValue> {
+        self.entries()
+            .last()
+            .filter(|entry| entry.name().is_none())
+            .map(|entry| entry.value())
+    }
+}
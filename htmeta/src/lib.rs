@@ -9,12 +9,20 @@ macro_rules! re {
     };
 }
 
-use std::{borrow::Cow, collections::HashMap, io::Write, rc::Rc};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io::Write,
+    ops::Range,
+    path::Path,
+    rc::Rc,
+};
 
 use dyn_clone::DynClone;
 pub use kdl;
 
-use kdl::{KdlDocument, KdlNode, KdlValue};
+use kdl::{KdlDocument, KdlEntry, KdlNode, KdlValue};
 use regex::Captures;
 
 /// Convenient alias for a [`std::io::Write`] mutable reference.
@@ -53,6 +61,39 @@ pub struct PluginMutContext<'a, 'b: 'a> {
     pub emitter: &'a mut HtmlEmitter<'a>,
 }
 
+/// Information about the document about to be emitted, given to [`IPlugin::on_start`].
+#[non_exhaustive]
+pub struct EmitInfo<'a> {
+    /// The whole document.
+    pub document: &'a KdlDocument,
+    /// The document's path, if it was set with [`HtmlEmitter::reset_for`].
+    pub filename: Option<&'a Path>,
+    defines: &'a HashSet<Box<str>>,
+}
+
+impl EmitInfo<'_> {
+    /// Returns `true` if `flag` was defined through [`HtmlEmitterBuilder::define`], so
+    /// plugins can tell which `@when` and `@unless` blocks will be emitted.
+    pub fn is_defined(&self, flag: &str) -> bool {
+        self.defines.contains(flag)
+    }
+}
+
+/// A problem with a document that doesn't stop it from being emitted.
+/// Check out [`HtmlEmitter::warn`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub message: String,
+    /// Where the warning's node is in the source document, as a range of bytes.
+    pub span: Option<Range<usize>>,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum EmitStatus {
     Skip,
@@ -67,6 +108,46 @@ pub trait IPlugin: DynClone {
         let _ = (node, context);
         unimplemented!("")
     }
+    /// Called by [`HtmlEmitter::emit`] before anything is emitted. Plugins can add variables
+    /// to `vars` for the document to use, like the build time, or collect nodes ahead of time
+    /// from the whole document in `info`.
+    fn on_start(&self, vars: &mut Vars, info: &EmitInfo) -> EmitResult {
+        let _ = (vars, info);
+        Ok(())
+    }
+    /// Called once the whole document was emitted by [`HtmlEmitter::emit`]. Plugins that
+    /// accumulate content can write it here.
+    ///
+    /// Note that each emitter has its own copy of the plugin, so state that must survive
+    /// until here has to be shared, e.g. through an [`Rc`].
+    fn on_end(&self, context: PluginContext) -> EmitResult {
+        let _ = context;
+        Ok(())
+    }
+    /// Called right before `</head>` is written, after the `head`'s children. Plugins that
+    /// collect content for the `head`, like styles, can write it here. `context.indent` is the
+    /// indentation of the `head`'s children.
+    ///
+    /// Tags with a text argument, like `head "..."`, aren't reported.
+    fn on_head_end(&self, context: PluginContext) -> EmitResult {
+        let _ = context;
+        Ok(())
+    }
+    /// Called right before `</body>` is written, like [`Self::on_head_end`]. Useful for
+    /// content that belongs at the end of the page, like scripts.
+    fn on_body_end(&self, context: PluginContext) -> EmitResult {
+        let _ = context;
+        Ok(())
+    }
+}
+
+/// Changes a document's `HTML` once it is fully emitted, for work that needs to see the
+/// whole page, like inlining the CSS it uses.
+///
+/// Check out [`HtmlEmitterBuilder::add_post_processor`].
+pub trait PostProcessor {
+    /// Returns the changed `html`.
+    fn process(&self, html: String) -> EmitResult<String>;
 }
 
 type Text<'b> = Cow<'b, str>;
@@ -84,20 +165,95 @@ impl Plugin {
     }
 }
 
+type NodeMatcher = Rc<dyn Fn(&KdlNode) -> bool>;
+type NodeHandler = Rc<dyn Fn(&KdlNode, PluginContext) -> EmitResult>;
+
+/// A plugin made of two closures. Check out [`HtmlEmitterBuilder::add_fn_plugin`].
+#[derive(Clone)]
+struct FnPlugin {
+    matcher: NodeMatcher,
+    emit: NodeHandler,
+}
+
+impl IPlugin for FnPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        if !(self.matcher)(node) {
+            return Ok(EmitStatus::Skip);
+        }
+        (self.emit)(node, context)?;
+        Ok(EmitStatus::Emmited)
+    }
+}
+
+mod attributes;
+mod compat;
+mod compiled;
+pub mod css;
+mod documents;
+mod email;
 mod error;
+mod expr;
+mod files;
+mod filters;
+pub mod kdl_ext;
+mod links;
+mod whitespace;
 
+pub use attributes::{DuplicateAttributes, InvalidAttributes};
+pub use compat::{parse_document, translate_v1};
+pub use compiled::CompiledDoc;
+pub use documents::split_documents;
+pub use email::{Email, OutputMode};
 pub use error::Error;
+pub use files::{EmbeddedFiles, FileProvider, MemoryFiles, RealFiles};
+pub use links::{LinkNormalization, TrailingSlash};
+pub use whitespace::TextWhitespace;
+
+/// How deep documents can be nested unless changed with [`HtmlEmitterBuilder::max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 256;
 
 const VOID_TAGS: &[&str] = &[
     "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
     "track", "wbr", "!DOCTYPE", // not a tag at all, but works a lot like one.
 ];
 
+/// The order attributes are emitted in by [`HtmlEmitterBuilder::sort_attributes`]. Entries
+/// ending in `*` match any attribute starting with them. Attributes that don't match any
+/// entry come last, and ties are sorted alphabetically.
+const ATTRIBUTE_ORDER: &[&str] = &["id", "class", "name", "data-*"];
+
+/// Returns where an attribute called `key` goes in [`ATTRIBUTE_ORDER`].
+fn attribute_rank(key: &str) -> usize {
+    ATTRIBUTE_ORDER
+        .iter()
+        .position(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == *pattern,
+        })
+        .unwrap_or(ATTRIBUTE_ORDER.len())
+}
+
 /// A builder for [`HtmlEmitter`]s.
 #[derive(Clone, Default)]
 pub struct HtmlEmitterBuilder {
     indent: Option<Indent>,
     plugins: Vec<Plugin>,
+    defines: Rc<HashSet<Box<str>>>,
+    preserve_entities: bool,
+    links: LinkNormalization,
+    text_whitespace: TextWhitespace,
+    env: bool,
+    missing_var: Option<MissingVarHook>,
+    files: Option<Rc<dyn FileProvider>>,
+    csp_nonce: Option<NonceSource>,
+    csp_meta: bool,
+    max_depth: Option<usize>,
+    warn_unused: bool,
+    sort_attributes: bool,
+    duplicate_attributes: DuplicateAttributes,
+    invalid_attributes: InvalidAttributes,
+    post_processors: Vec<Rc<dyn PostProcessor>>,
+    mode: OutputMode,
 }
 
 impl HtmlEmitterBuilder {
@@ -130,12 +286,201 @@ impl HtmlEmitterBuilder {
         self
     }
 
+    /// Keeps valid character entity references, like `&nbsp;` or `&#169;`, untouched in text
+    /// nodes instead of escaping them. Raw `<`, `>` and bare `&` are still escaped.
+    pub fn preserve_entities(&mut self) -> &mut Self {
+        self.preserve_entities = true;
+        self
+    }
+
+    /// Rewrites internal links in `href` and `action` attributes, so every page links
+    /// to others consistently. Check out [`LinkNormalization`] for the available options.
+    pub fn normalize_links(&mut self, links: LinkNormalization) -> &mut Self {
+        self.links = links;
+        self
+    }
+
+    /// Decides what happens to variables that aren't set, which expand to an empty string
+    /// by default. `hook` is called with each variable's name, without the `$`.
+    ///
+    /// Expressions, like `${ $name }`, keep reporting unset variables as warnings.
+    ///
+    /// # Example
+    /// ```rust
+    /// use htmeta::{Error, HtmlEmitter, MissingVarAction};
+    /// let mut builder = HtmlEmitter::builder();
+    /// builder.minify().on_missing_var(|name| match name {
+    ///     "title" => MissingVarAction::Default("Untitled".into()),
+    ///     _ => MissingVarAction::Error,
+    /// });
+    /// let doc = r#"h1 "$title""#.parse().unwrap();
+    /// let mut result = Vec::<u8>::new();
+    /// builder.build().emit(&doc, &mut result).unwrap();
+    /// assert_eq!(result, b"<h1>Untitled</h1>");
+    ///
+    /// let doc = r#"p "$typo""#.parse().unwrap();
+    /// let error = builder.build().emit(&doc, &mut Vec::new()).unwrap_err();
+    /// assert_eq!(error, Error::MissingVariable { name: "typo".into() });
+    /// ```
+    pub fn on_missing_var(
+        &mut self,
+        hook: impl Fn(&str) -> MissingVarAction + 'static,
+    ) -> &mut Self {
+        self.missing_var = Some(MissingVarHook(Rc::new(hook)));
+        self
+    }
+
+    /// Cleans up the whitespace of text nodes, like multi-line strings indented to fit the
+    /// document. Check out [`TextWhitespace`] for the available options.
+    pub fn text_whitespace(&mut self, whitespace: TextWhitespace) -> &mut Self {
+        self.text_whitespace = whitespace;
+        self
+    }
+
+    /// Sets where plugins read files from, like icons or stylesheets. Defaults to
+    /// [`RealFiles`], which reads them from disk.
+    pub fn files<F: FileProvider + 'static>(&mut self, files: F) -> &mut Self {
+        self.files = Some(Rc::new(files));
+        self
+    }
+
+    /// Adds a `nonce` attribute to every `script` and `style` tag, so a
+    /// `Content-Security-Policy` can allow them while blocking injected ones. `source` is
+    /// called for each document, and must return an unguessable value, like random bytes
+    /// encoded in base64.
+    ///
+    /// The matching policy is returned by [`HtmlEmitter::content_security_policy`], and
+    /// can be added to the page with [`Self::csp_meta`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use htmeta::HtmlEmitter;
+    /// let mut builder = HtmlEmitter::builder();
+    /// builder.minify().csp_nonce(|| "cmFuZG9t".into());
+    /// let doc = r#"script src="app.js""#.parse().unwrap();
+    /// let mut emitter = builder.build();
+    /// let mut result = Vec::<u8>::new();
+    /// emitter.emit(&doc, &mut result).unwrap();
+    /// assert_eq!(result, br#"<script src="app.js" nonce="cmFuZG9t"></script>"#);
+    /// assert_eq!(
+    ///     emitter.content_security_policy().unwrap(),
+    ///     "script-src 'nonce-cmFuZG9t'; style-src 'nonce-cmFuZG9t'"
+    /// );
+    /// ```
+    pub fn csp_nonce(&mut self, source: impl Fn() -> String + 'static) -> &mut Self {
+        self.csp_nonce = Some(NonceSource(Rc::new(source)));
+        self
+    }
+
+    /// Adds the document's `Content-Security-Policy` as a `meta` tag at the start of its
+    /// `head`. Does nothing unless [`Self::csp_nonce`] is set.
+    pub fn csp_meta(&mut self) -> &mut Self {
+        self.csp_meta = true;
+        self
+    }
+
+    /// Emits attributes in a canonical order, regardless of the order they were written in:
+    /// `id`, `class`, `name`, `data-*` attributes and then every other one, alphabetically.
+    /// This keeps diffs of the output stable and helps it compress better.
+    pub fn sort_attributes(&mut self) -> &mut Self {
+        self.sort_attributes = true;
+        self
+    }
+
+    /// Chooses what happens when a node sets the same attribute more than once. Check out
+    /// [`DuplicateAttributes`] for the available policies.
+    pub fn duplicate_attributes(&mut self, policy: DuplicateAttributes) -> &mut Self {
+        self.duplicate_attributes = policy;
+        self
+    }
+
+    /// Chooses what happens when an attribute's name isn't valid `HTML`, like one set through
+    /// a variable. Check out [`InvalidAttributes`] for the available policies.
+    pub fn invalid_attributes(&mut self, policy: InvalidAttributes) -> &mut Self {
+        self.invalid_attributes = policy;
+        self
+    }
+
+    /// Lets documents read environment variables through `$env:NAME`. Unset variables
+    /// expand to an empty string.
+    pub fn env_vars(&mut self) -> &mut Self {
+        self.env = true;
+        self
+    }
+
+    /// Sets how many levels deep nodes can be nested before emitting fails with
+    /// [`Error::TooDeep`]. This keeps runaway documents, like a template that instantiates
+    /// itself, from overflowing the stack. Defaults to [`DEFAULT_MAX_DEPTH`].
+    pub fn max_depth(&mut self, max_depth: usize) -> &mut Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Warns about variables that are set but never used, and lets plugins warn about
+    /// similar mistakes, like unused template parameters. Check out [`HtmlEmitter::warn`].
+    pub fn warn_unused(&mut self) -> &mut Self {
+        self.warn_unused = true;
+        self
+    }
+
     /// Registers a plugin for all instances of this builder.
     pub fn add_plugin<P: IPlugin + 'static>(&mut self, plugin: P) -> &mut Self {
         self.plugins.push(Plugin::new(plugin));
         self
     }
 
+    /// Registers a plugin made of two closures, for quick handlers that don't need a type
+    /// of their own: `emit` is called for every node `matcher` returns `true` for, and
+    /// other nodes are left to the rest of the plugins.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::io::Write;
+    /// use htmeta::HtmlEmitter;
+    /// let mut builder = HtmlEmitter::builder();
+    /// builder.minify().add_fn_plugin(
+    ///     |node| node.name().value() == "@year",
+    ///     |_, context| Ok(write!(context.writer, "2024")?),
+    /// );
+    /// let doc = r#"footer { @year }"#.parse().unwrap();
+    /// let mut result = Vec::<u8>::new();
+    /// builder.build().emit(&doc, &mut result).unwrap();
+    /// assert_eq!(result, b"<footer>2024</footer>");
+    /// ```
+    pub fn add_fn_plugin(
+        &mut self,
+        matcher: impl Fn(&KdlNode) -> bool + 'static,
+        emit: impl Fn(&KdlNode, PluginContext) -> EmitResult + 'static,
+    ) -> &mut Self {
+        self.add_plugin(FnPlugin {
+            matcher: Rc::new(matcher),
+            emit: Rc::new(emit),
+        })
+    }
+
+    /// Registers a post-processor, which changes the whole output of each document. They
+    /// run in the order they were added.
+    ///
+    /// Note that the output is kept in memory until every post-processor is done.
+    pub fn add_post_processor<P: PostProcessor + 'static>(&mut self, processor: P) -> &mut Self {
+        self.post_processors.push(Rc::new(processor));
+        self
+    }
+
+    /// Chooses where the pages are shown. [`OutputMode::Email`] runs [`Email`] after every
+    /// other post-processor, so pages work in email clients.
+    pub fn mode(&mut self, mode: OutputMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Defines a build flag, which enables `@when "flag"` blocks and disables
+    /// `@unless "flag"` ones.
+    pub fn define(&mut self, flag: &str) -> &mut Self {
+        Rc::make_mut(&mut self.defines).insert(flag.into());
+        self
+    }
+
     /// Creates a new [`HtmlEmitter`]. You should re-use this builder to create emitters
     /// efficiently.
     pub fn build<'a>(&self) -> HtmlEmitter<'a> {
@@ -143,38 +488,289 @@ impl HtmlEmitterBuilder {
             current_level: 0,
             indent: self.indent,
             plugins: self.plugins.clone(),
-            vars: Default::default(),
+            defines: self.defines.clone(),
+            preserve_entities: self.preserve_entities,
+            links: self.links,
+            text_whitespace: self.text_whitespace,
+            sort_attributes: self.sort_attributes,
+            duplicate_attributes: self.duplicate_attributes,
+            invalid_attributes: self.invalid_attributes,
+            post_processors: match self.mode {
+                OutputMode::Web => self.post_processors.clone(),
+                OutputMode::Email => {
+                    let mut post_processors = self.post_processors.clone();
+                    post_processors.push(Rc::new(Email));
+                    post_processors
+                }
+            },
+            files: self.files.clone().unwrap_or_else(|| Rc::new(RealFiles)),
+            nonce_source: self.csp_nonce.clone(),
+            nonce: None,
+            csp_meta: self.csp_meta,
+            depth: 0,
+            index: 0,
+            previous: None,
+            warn_unused: self.warn_unused,
+            warnings: Default::default(),
+            filename: None,
+            max_depth: self.max_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+            vars: Vars {
+                env: self.env,
+                missing_var: self.missing_var.clone(),
+                track_usage: self.warn_unused,
+                ..Default::default()
+            },
         }
     }
 }
 
 type VarMap<'content> = HashMap<Box<str>, Text<'content>>;
 
+/// What to do with a variable that isn't set. Check out [`HtmlEmitterBuilder::on_missing_var`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum MissingVarAction {
+    /// Expands to an empty string.
+    #[default]
+    EmptyString,
+    /// Keeps the variable as written, like `$name`.
+    KeepLiteral,
+    /// Expands to the given text.
+    Default(String),
+    /// Fails with [`Error::MissingVariable`].
+    Error,
+}
+
+/// Creates the `Content-Security-Policy` nonce of each document.
+#[derive(Clone)]
+struct NonceSource(Rc<dyn Fn() -> String>);
+
+/// Decides what to do with each variable that isn't set.
+#[derive(Clone)]
+struct MissingVarHook(Rc<dyn Fn(&str) -> MissingVarAction>);
+
+impl std::fmt::Debug for MissingVarHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MissingVarHook")
+    }
+}
+
+/// Which variables were set and used, shared by every scope of a document.
+#[derive(Debug, Default)]
+struct VarUsage {
+    /// Variables set by variable nodes, in order.
+    set: Vec<Box<str>>,
+    /// The same variables as [`Self::set`], to quickly check if one is there.
+    set_lookup: HashSet<Box<str>>,
+    used: HashSet<Box<str>>,
+    /// Expressions that couldn't be evaluated, as warnings to be reported by the emitter.
+    errors: Vec<String>,
+    /// The first variable that wasn't set and should fail the emitter.
+    missing: Option<String>,
+}
+
+/// Escapes `text` so it can be safely used as `HTML` text content, keeping valid character
+/// entity references if `preserve_entities` is set.
+pub(crate) fn escape_text(text: &str, preserve_entities: bool) -> Text<'_> {
+    if !preserve_entities {
+        return html_escape::encode_text(text);
+    }
+    re!(
+        ENTITY,
+        r"&(?:[A-Za-z][A-Za-z0-9]*|#[0-9]+|#[xX][0-9A-Fa-f]+);"
+    );
+    let mut escaped = String::with_capacity(text.len());
+    let mut last = 0;
+    for entity in ENTITY.find_iter(text) {
+        // Only keep entities that actually mean something
+        if html_escape::decode_html_entities(entity.as_str()) == entity.as_str() {
+            continue;
+        }
+        escaped.push_str(&html_escape::encode_text(&text[last..entity.start()]));
+        escaped.push_str(entity.as_str());
+        last = entity.end();
+    }
+    if last == 0 {
+        return html_escape::encode_text(text);
+    }
+    escaped.push_str(&html_escape::encode_text(&text[last..]));
+    escaped.into()
+}
+
 /// Holds all node's variables
 #[derive(Clone, Debug, Default)]
 pub struct Vars<'content> {
     vars: Rc<VarMap<'content>>,
+    /// When set, missing variables are kept as markers for [`CompiledDoc`] instead of being
+    /// expanded into empty strings.
+    pub(crate) defer_missing: bool,
+    /// Whether `$env:NAME` reads from the process environment.
+    pub(crate) env: bool,
+    missing_var: Option<MissingVarHook>,
+    usage: Rc<RefCell<VarUsage>>,
+    /// Whether variables are tracked in [`Self::usage`] as they are set and used, which is
+    /// only needed to warn about unused ones.
+    track_usage: bool,
+    /// Variables whose values come from outside the document, like user input.
+    untrusted: Rc<HashSet<Box<str>>>,
+    /// Whether the values of [`Self::untrusted`] variables are escaped when expanded.
+    escape_untrusted: bool,
 }
 
 impl<'content> Vars<'content> {
     /// Replaces all occurences of variables inside `text` and returns a new string.
+    ///
+    /// Variables can be followed by filters, like `$price|fixed:2`. The supported ones are:
+    ///  - `fixed:N`: formats a number with `N` decimal places.
+    ///  - `thousands:SEP`: groups a number's digits by thousands with `SEP`, which defaults to `,`.
+    ///
+    /// If enabled through [`HtmlEmitterBuilder::env_vars`], `$env:NAME` expands to the `NAME`
+    /// environment variable.
+    ///
+    /// `${ ... }` expressions are evaluated, like `${ $price * $amount }`, and can be followed
+    /// by filters too. Expressions that can't be evaluated expand to an empty string, and
+    /// are reported as warnings once the document is emitted. Text that isn't a valid
+    /// expression, like `${name}` in JavaScript, is left as-is.
     pub fn expand_string<'b>(&self, text: &'b str) -> Text<'b> {
-        re!(VAR, r"\$(\w+)");
-        VAR.replace(text, |captures: &Captures| {
-            self.vars
-                .get(&captures[1])
-                .map(ToString::to_string)
-                .unwrap_or_default()
+        if !text.contains("${") {
+            return self.expand_vars(text);
+        }
+        re!(
+            FILTERS,
+            r"^(?:\|(?:fixed|thousands)(?::(?:\w+|[^\w\s|]))?)*"
+        );
+        let mut result = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("${")
+            && let Some(len) = expr::len(&rest[start + 2..])
+        {
+            result.push_str(&self.expand_vars(&rest[..start]));
+            let end = start + 2 + len + 1;
+            let filters = FILTERS.find(&rest[end..]).map_or("", |m| m.as_str());
+            let source = &rest[start..end + filters.len()];
+            let value = match expr::eval(&rest[start + 2..end - 1], self) {
+                Ok(value) => filters::apply_chain(value, filters),
+                // Probably not meant as an expression, like JavaScript's template literals
+                Err(expr::ExprError::Syntax(_)) => {
+                    result.push_str(&rest[start..end]);
+                    rest = &rest[end..];
+                    continue;
+                }
+                Err(expr::ExprError::Missing(_)) if self.defer_missing => format!(
+                    "{}{source}{}",
+                    compiled::DEFERRED_START,
+                    compiled::DEFERRED_END
+                ),
+                Err(err) => {
+                    self.usage
+                        .borrow_mut()
+                        .errors
+                        .push(format!("{source}: {err}"));
+                    String::new()
+                }
+            };
+            result.push_str(&value);
+            rest = &rest[end + filters.len()..];
+        }
+        result.push_str(&self.expand_vars(rest));
+        result.into()
+    }
+
+    /// Replaces the variables inside `text`, but not expressions.
+    fn expand_vars<'b>(&self, text: &'b str) -> Text<'b> {
+        re!(
+            VAR,
+            r"\$((?:env:)?\w+)((?:\|(?:fixed|thousands)(?::(?:\w+|[^\w\s|]))?)*)"
+        );
+        VAR.replace_all(text, |captures: &Captures| {
+            let value = match captures[1].strip_prefix("env:") {
+                Some(name) if self.env => Some(std::env::var(name).unwrap_or_default().into()),
+                _ => self.get(&captures[1]).cloned(),
+            };
+            match value {
+                Some(value) => self.escape_if_untrusted(
+                    &captures[1],
+                    filters::apply_chain(value.to_string(), &captures[2]),
+                ),
+                None if self.defer_missing => format!(
+                    "{}{}{}",
+                    compiled::DEFERRED_START,
+                    &captures[0],
+                    compiled::DEFERRED_END
+                ),
+                None => match self.missing_var.as_ref().map(|hook| hook.0(&captures[1])) {
+                    None | Some(MissingVarAction::EmptyString) => String::new(),
+                    Some(MissingVarAction::KeepLiteral) => captures[0].to_string(),
+                    Some(MissingVarAction::Default(value)) => {
+                        filters::apply_chain(value, &captures[2])
+                    }
+                    Some(MissingVarAction::Error) => {
+                        let mut usage = self.usage.borrow_mut();
+                        usage.missing.get_or_insert_with(|| captures[1].into());
+                        String::new()
+                    }
+                },
+            }
         })
     }
 
     /// Converts the `value`'s [`String`] representation and replaces any variables found within.
     /// This is a convenient wrapper around [`Self::expand_string`].
+    ///
+    /// Numbers are formatted without representation noise or scientific notation, and
+    /// `#null` becomes an empty string.
     pub fn expand_value<'b>(&self, value: &'b KdlValue) -> Text<'b> {
         match value {
             KdlValue::String(content) => self.expand_string(content),
-            _ => todo!(),
+            KdlValue::Integer(int) => int.to_string().into(),
+            KdlValue::Float(float) => filters::format_float(*float).into(),
+            KdlValue::Bool(boolean) => boolean.to_string().into(),
+            KdlValue::Null => "".into(),
+        }
+    }
+
+    /// Like [`Self::expand_value`], for values that decide what gets emitted, like a
+    /// `@when` flag. While compiling a [`CompiledDoc`], fails with
+    /// [`Error::DeferredVariable`] if `value` uses variables that are only set when emitting
+    /// it, as their value isn't known yet.
+    pub fn expand_known<'b>(&self, node: &str, value: &'b KdlValue) -> EmitResult<Text<'b>> {
+        let expanded = self.expand_value(value);
+        if self.defer_missing && expanded.contains(compiled::DEFERRED_START) {
+            return Err(Error::DeferredVariable { node: node.into() });
+        }
+        Ok(expanded)
+    }
+
+    /// Like [`Self::expand_value`], but the values of untrusted variables are escaped. Used
+    /// for text that is written into the page as-is, like a node's text argument.
+    pub fn expand_content<'b>(&self, value: &'b KdlValue) -> Text<'b> {
+        if self.untrusted.is_empty() {
+            return self.expand_value(value);
         }
+        let mut vars = self.clone();
+        vars.escape_untrusted = true;
+        vars.expand_value(value)
+    }
+
+    /// Returns `value`, the expanded value of `key`, escaped if it is untrusted and
+    /// untrusted variables are being escaped.
+    pub(crate) fn escape_if_untrusted(&self, key: &str, value: String) -> String {
+        match self.escape_untrusted && self.untrusted.contains(key) {
+            true => html_escape::encode_text(&value).into_owned(),
+            false => value,
+        }
+    }
+
+    /// Returns whether `value` uses any untrusted variable, in which case variables set
+    /// to it must be untrusted too.
+    pub fn uses_untrusted(&self, value: &KdlValue) -> bool {
+        re!(VAR_NAME, r"\$(\w+)");
+        let KdlValue::String(text) = value else {
+            return false;
+        };
+        !self.untrusted.is_empty()
+            && VAR_NAME
+                .captures_iter(text)
+                .any(|captures| self.untrusted.contains(&captures[1]))
     }
 
     fn make_mut(&mut self) -> &mut VarMap<'content> {
@@ -183,17 +779,66 @@ impl<'content> Vars<'content> {
 
     /// Inserts a new variable into the node.
     pub fn insert(&mut self, key: &str, value: Text<'content>) {
+        if self.untrusted.contains(key) {
+            Rc::make_mut(&mut self.untrusted).remove(key);
+        }
         self.make_mut().insert(key.into(), value);
     }
 
-    /// Returns a reference to a variable's value.
+    /// Inserts a new variable whose value can't be trusted, like user input. Its value is
+    /// escaped wherever it would otherwise end up in the page as-is.
+    pub fn insert_untrusted(&mut self, key: &str, value: Text<'content>) {
+        self.make_mut().insert(key.into(), value);
+        Rc::make_mut(&mut self.untrusted).insert(key.into());
+    }
+
+    /// Inserts `key` like [`Self::insert`], or like [`Self::insert_untrusted`] if `source`,
+    /// the value it was expanded from, uses untrusted variables.
+    pub fn insert_from(&mut self, key: &str, source: &KdlValue, value: Text<'content>) {
+        match self.uses_untrusted(source) {
+            true => self.insert_untrusted(key, value),
+            false => self.insert(key, value),
+        }
+    }
+
+    /// Returns a reference to a variable's value, marking it as used.
     pub fn get(&self, key: &str) -> Option<&Text<'content>> {
+        if self.track_usage {
+            let mut usage = self.usage.borrow_mut();
+            if !usage.used.contains(key) {
+                usage.used.insert(key.into());
+            }
+        }
         self.vars.get(key)
     }
 
+    /// Records that `key` was set by a variable node.
+    fn mark_set(&self, key: &str) {
+        if !self.track_usage {
+            return;
+        }
+        let mut usage = self.usage.borrow_mut();
+        if !usage.set_lookup.contains(key) {
+            usage.set_lookup.insert(key.into());
+            usage.set.push(key.into());
+        }
+    }
+
+    /// Returns the variables set by variable nodes that were never used, in order.
+    fn unused(&self) -> Vec<Box<str>> {
+        let usage = self.usage.borrow();
+        usage
+            .set
+            .iter()
+            .filter(|key| !usage.used.contains(*key))
+            .cloned()
+            .collect()
+    }
+
     /// Clears the node, removing all registered variables.
     pub fn clear(&mut self) {
         self.make_mut().clear();
+        Rc::make_mut(&mut self.untrusted).clear();
     }
 }
 
@@ -202,8 +847,9 @@ where
     S: Into<Box<str>>,
 {
     fn extend<T: IntoIterator<Item = (S, Text<'a>)>>(&mut self, iter: T) {
-        self.make_mut()
-            .extend(iter.into_iter().map(|(k, v)| (k.into(), v)))
+        for (key, value) in iter {
+            self.insert(&key.into(), value);
+        }
     }
 }
 
@@ -236,7 +882,35 @@ pub struct HtmlEmitter<'a> {
     pub current_level: Indent,
     /// Contains a node's variables.
     pub vars: Vars<'a>,
+    /// Whether valid character entity references are kept as-is when escaping text.
+    pub preserve_entities: bool,
+    /// How internal links are rewritten.
+    pub links: LinkNormalization,
+    /// How the whitespace of text nodes is cleaned up.
+    pub text_whitespace: TextWhitespace,
+    /// Whether attributes are emitted in a canonical order.
+    /// Check out [`HtmlEmitterBuilder::sort_attributes`].
+    pub sort_attributes: bool,
+    /// What happens when a node sets the same attribute more than once.
+    pub duplicate_attributes: DuplicateAttributes,
+    /// What happens when an attribute's name isn't valid `HTML`.
+    pub invalid_attributes: InvalidAttributes,
     plugins: Vec<Plugin>,
+    post_processors: Vec<Rc<dyn PostProcessor>>,
+    files: Rc<dyn FileProvider>,
+    nonce_source: Option<NonceSource>,
+    /// The current document's nonce, created once it starts being emitted.
+    nonce: Option<Rc<str>>,
+    csp_meta: bool,
+    defines: Rc<HashSet<Box<str>>>,
+    /// How many [`Self::emit_nodes`] calls we're nested in, counting plugins.
+    depth: usize,
+    max_depth: usize,
+    index: usize,
+    previous: Option<&'a str>,
+    warn_unused: bool,
+    warnings: Rc<RefCell<Vec<Warning>>>,
+    filename: Option<Rc<Path>>,
 }
 
 impl<'a> HtmlEmitter<'a> {
@@ -249,14 +923,118 @@ impl<'a> HtmlEmitter<'a> {
 
     /// Returns an [`HtmlEmitter`] with a copy of `self`'s variables and one indentation level
     /// deeper. This emitter should be used to translate a child of `self`.
-    pub fn subemitter<'b: 'a>(&'b self) -> HtmlEmitter<'b> {
+    pub fn subemitter(&self) -> HtmlEmitter<'a> {
         HtmlEmitter {
             current_level: self.current_level + 1,
+            index: 0,
+            previous: None,
             // node,
             ..self.clone()
         }
     }
 
+    /// Returns the index of the node being emitted within its parent's children.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the name of the last node that was emitted at this level, if any. Variable
+    /// nodes don't count, as they don't emit anything.
+    ///
+    /// Plugins can use this to treat consecutive nodes differently, like separating them
+    /// with commas.
+    pub fn previous_sibling(&self) -> Option<&'a str> {
+        self.previous
+    }
+
+    /// Returns the path of the document being emitted, if it was set with [`Self::reset_for`].
+    pub fn filename(&self) -> Option<&Path> {
+        self.filename.as_deref()
+    }
+
+    /// Forgets everything about the previous document, leaving the emitter as it was when
+    /// created by its builder. That is, variables, warnings and the filename are cleared,
+    /// while settings and plugins are kept.
+    ///
+    /// Plugins keep their own state, and are expected to reset it in [`IPlugin::on_start`].
+    pub fn reset(&mut self) {
+        self.vars.clear();
+        self.vars.usage = Default::default();
+        self.warnings = Default::default();
+        self.filename = None;
+        self.nonce = None;
+        self.index = 0;
+        self.previous = None;
+    }
+
+    /// Like [`Self::reset`], but prepares the emitter for the document at `filename`.
+    pub fn reset_for(&mut self, filename: impl AsRef<Path>) {
+        self.reset();
+        self.filename = Some(filename.as_ref().into());
+    }
+
+    /// Returns where plugins should read files from, as set by
+    /// [`HtmlEmitterBuilder::files`].
+    pub fn files(&self) -> &dyn FileProvider {
+        &*self.files
+    }
+
+    /// Returns the nonce given to the document's `script` and `style` tags, if
+    /// [`HtmlEmitterBuilder::csp_nonce`] was set. A new one is created every time a document
+    /// starts being emitted, and kept until the next one or until the emitter is reset.
+    pub fn csp_nonce(&self) -> Option<&str> {
+        self.nonce.as_deref()
+    }
+
+    /// Returns the `Content-Security-Policy` that allows the document's `script` and `style`
+    /// tags, if [`HtmlEmitterBuilder::csp_nonce`] was set. Servers can send it as a header.
+    pub fn content_security_policy(&self) -> Option<String> {
+        self.csp_nonce()
+            .map(|nonce| format!("script-src 'nonce-{nonce}'; style-src 'nonce-{nonce}'"))
+    }
+
+    /// Returns `true` if unused variables and parameters should be warned about, as enabled
+    /// by [`HtmlEmitterBuilder::warn_unused`].
+    pub fn warns_unused(&self) -> bool {
+        self.warn_unused
+    }
+
+    /// Records a warning about the document. Warnings don't stop emitting, and are shared by
+    /// every emitter of a document. Repeated warnings are only recorded once.
+    pub fn warn(&self, message: impl Into<String>) {
+        self.push_warning(Warning {
+            message: message.into(),
+            span: None,
+        });
+    }
+
+    /// Like [`Self::warn`], but points the warning at `node`, so it can be shown next to
+    /// the node's source.
+    pub fn warn_at(&self, message: impl Into<String>, node: &KdlNode) {
+        let span = node.span();
+        self.push_warning(Warning {
+            message: message.into(),
+            span: Some(span.offset()..span.offset() + span.len()),
+        });
+    }
+
+    fn push_warning(&self, warning: Warning) {
+        let mut warnings = self.warnings.borrow_mut();
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+
+    /// Returns and clears the warnings recorded so far.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings.borrow_mut())
+    }
+
+    /// Returns `true` if `flag` was defined through [`HtmlEmitterBuilder::define`].
+    pub fn is_defined(&self, flag: &str) -> bool {
+        self.defines.contains(flag)
+    }
+
     /// Returns `true` if in minify mode, `false` otherwise.
     pub fn is_minify(&self) -> bool {
         self.indent == Some(0)
@@ -276,8 +1054,10 @@ impl<'a> HtmlEmitter<'a> {
     /// # Example
     /// ```rust
     /// use htmeta::HtmlEmitter;
+    /// use htmeta::kdl::KdlNode;
     /// let emitter = HtmlEmitter::builder().indent(4).build();
-    /// assert_eq!(emitter.indent(), "");
+    /// let node = "p".parse::<KdlNode>().unwrap();
+    /// assert_eq!(emitter.indent(&node), "");
     /// ```
     pub fn indent(&self, node: &KdlNode) -> String {
         match self.indent {
@@ -306,17 +1086,17 @@ impl<'a> HtmlEmitter<'a> {
     /// emitter.emit_tag(&node, node.name().value(), "", &mut result).unwrap();
     /// assert_eq!(result, br#"<p id="paragraph">Hello, world!</p>"#);
     /// ```
-    pub fn emit_tag<'b: 'a>(
-        &'a self,
+    pub fn emit_tag(
+        &self,
         node: &'a KdlNode,
         name: &str,
         indent: &str,
-        writer: Writer<'b>,
+        writer: Writer,
     ) -> EmitResult {
         let is_void = VOID_TAGS.contains(&name);
-
-        // opening tag
-        write!(writer, "{}<{}", indent, name)?;
+        if is_void && node.children().is_some() {
+            return Err(Error::VoidTagWithChildren { tag: name.into() });
+        }
 
         let mut entries = node.entries().to_vec();
 
@@ -327,17 +1107,39 @@ impl<'a> HtmlEmitter<'a> {
             contents = Some(entry);
 
             if node.children().is_some() {
-                return Err("Nodes with inline text and children aren't allowed.")?;
+                return Err(Error::MixedContent { tag: name.into() });
             }
         }
 
+        let entries = self.expand_attribute_names(entries);
+        let mut entries = self.duplicate_attributes.apply(entries, name)?;
+        if let Some(nonce) = &self.nonce
+            && (name == "script" || name == "style")
+            && !entries
+                .iter()
+                .any(|entry| entry.name().is_some_and(|key| key.value() == "nonce"))
+        {
+            let mut entry = KdlEntry::new_prop("nonce", nonce.to_string());
+            entry.autoformat();
+            entries.push(entry);
+        }
+        if self.sort_attributes {
+            // Bare arguments have no name, and are kept in place before every attribute
+            entries.sort_by_cached_key(|entry| {
+                entry
+                    .name()
+                    .map(|key| (attribute_rank(key.value()), key.value().to_string()))
+            });
+        }
+
         let args = entries
-            .into_iter()
-            .map(|arg| self.vars.expand_string(&arg.to_string()).into_owned())
-            .collect::<Vec<_>>()
+            .iter()
+            .map(|arg| self.format_attribute(node, arg))
+            .collect::<EmitResult<Vec<_>>>()?
             .join("");
 
-        write!(writer, "{}", args)?;
+        // opening tag
+        write!(writer, "{}<{}{}", indent, name, args)?;
 
         if is_void {
             write!(writer, ">")?;
@@ -346,21 +1148,129 @@ impl<'a> HtmlEmitter<'a> {
             write!(writer, ">")?;
             if let Some(contents) = contents {
                 // If node has children and text, print each in their own line
-                write!(writer, "{}", self.vars.expand_value(contents.value()))?;
+                write!(writer, "{}", self.vars.expand_content(contents.value()))?;
             }
             // Children
             else if let Some(doc) = node.children() {
                 self.write_line(writer)?;
                 let mut value = self.subemitter();
-                value.emit(doc, writer)?;
+                if name == "head" && self.csp_meta {
+                    value.emit_csp_meta(doc, writer)?;
+                }
+                value.emit_nodes(doc.nodes(), writer)?;
+                if name == "head" || name == "body" {
+                    value.call_plugins_boundary(name, Some(doc), writer)?;
+                }
                 write!(writer, "{}", indent)?;
             }
+            // Plugins can still add to empty ones
+            else if name == "head" || name == "body" {
+                let mut buf = Vec::<u8>::new();
+                self.subemitter()
+                    .call_plugins_boundary(name, None, &mut buf)?;
+                if !buf.is_empty() {
+                    self.write_line(writer)?;
+                    writer.write_all(&buf)?;
+                    write!(writer, "{}", indent)?;
+                }
+            }
             write!(writer, "</{}>", name)?;
             self.write_line(writer)?;
         }
         Ok(())
     }
 
+    /// Returns `entries` with the variables in their names expanded, so attributes are
+    /// checked for duplicates by the names they end up with.
+    fn expand_attribute_names(&self, entries: Vec<KdlEntry>) -> Vec<KdlEntry> {
+        entries
+            .into_iter()
+            .map(|entry| match entry.name() {
+                Some(key) if key.value().contains('$') => {
+                    let name = self.vars.expand_string(key.value()).into_owned();
+                    let mut expanded = KdlEntry::new_prop(name, entry.value().clone());
+                    expanded.autoformat();
+                    if let (Some(format), Some(original)) = (expanded.format_mut(), entry.format())
+                    {
+                        format.leading = original.leading.clone();
+                    }
+                    expanded
+                }
+                _ => entry,
+            })
+            .collect()
+    }
+
+    /// Returns `entry` as an `HTML` attribute, with variables expanded and links normalized.
+    /// Bare arguments become attributes without a value, like `disabled`. Attributes with
+    /// invalid names are handled according to [`Self::invalid_attributes`].
+    fn format_attribute(&self, node: &KdlNode, entry: &KdlEntry) -> EmitResult<String> {
+        let text = entry.to_string();
+        let leading = &text[..text.len() - text.trim_start().len()];
+        let name = match entry.name() {
+            Some(key) => Cow::Borrowed(key.value()),
+            None => match entry.value() {
+                KdlValue::String(_) => self.vars.expand_value(entry.value()),
+                _ => return Ok(text),
+            },
+        };
+        if !attributes::is_valid_name(&name) {
+            let tag = node.name().value();
+            match self.invalid_attributes {
+                InvalidAttributes::Error => {
+                    return Err(Error::InvalidAttributeName {
+                        tag: tag.into(),
+                        attribute: name.into_owned(),
+                    })
+                }
+                InvalidAttributes::Skip => {
+                    let message =
+                        format!("{tag}: Skipped `{name}`, as it is not a valid attribute name.");
+                    self.warn_at(message, node);
+                    return Ok(String::new());
+                }
+                InvalidAttributes::Allow => (),
+            }
+        }
+        if entry.name().is_none() {
+            return Ok(format!("{leading}{name}"));
+        }
+        if !matches!(entry.value(), KdlValue::String(_)) {
+            // Numbers, booleans and `#null` are written as they are in KDL
+            return Ok(format!("{leading}{name}={}", entry.value()));
+        }
+        let value = match !self.links.is_noop() && links::LINK_ATTRIBUTES.contains(&&*name) {
+            // Links can't be normalized before their variables are known
+            true => {
+                let link = self.vars.expand_known(node.name().value(), entry.value())?;
+                Cow::Owned(self.links.normalize(&link))
+            }
+            false => self.vars.expand_value(entry.value()),
+        };
+        Ok(format!(
+            "{leading}{name}=\"{}\"",
+            compiled::mark_escaped(html_escape::encode_double_quoted_attribute(&value))
+        ))
+    }
+
+    /// Writes the document's `Content-Security-Policy` as a `meta` tag. `self` must be the
+    /// emitter of the `head`'s children, in `doc`.
+    fn emit_csp_meta(&self, doc: &KdlDocument, writer: Writer) -> EmitResult {
+        let Some(policy) = self.content_security_policy() else {
+            return Ok(());
+        };
+        let indent = doc
+            .nodes()
+            .first()
+            .map(|first| self.indent(first))
+            .unwrap_or_default();
+        write!(
+            writer,
+            r#"{indent}<meta http-equiv="Content-Security-Policy" content="{policy}">"#
+        )?;
+        self.write_line(writer)
+    }
+
     fn call_plugin(
         &mut self,
         node: &KdlNode,
@@ -400,34 +1310,86 @@ impl<'a> HtmlEmitter<'a> {
         Ok(false)
     }
 
+    /// Escapes `text` so it can be safely used as `HTML` text content.
+    ///
+    /// If [`Self::preserve_entities`] is set, valid character entity references are kept.
+    pub fn escape_text<'b>(&self, text: &'b str) -> Text<'b> {
+        escape_text(text, self.preserve_entities)
+    }
+
     /// Simply emits the given text content in `content` into the `writer`, indented by the
     /// `indent` param.
     ///
     /// # Example
     /// ```
-    /// use kdl::KdlValue;
+    /// use kdl::{KdlNode, KdlValue};
     /// use htmeta::HtmlEmitter;
     /// let emitter = HtmlEmitter::builder().indent(4).build();
     /// let mut writer = Vec::<u8>::new();
     /// // Usually this value is given to you by other functions.
-    /// let indent = emitter.indent();
+    /// let indent = emitter.indent(&"-".parse::<KdlNode>().unwrap());
     /// let value = KdlValue::String("I'm text".into());
     /// emitter.emit_text_node(&indent, &value, &mut writer).unwrap();
     /// assert_eq!(writer, b"I'm text\n");
     /// ```
     pub fn emit_text_node(&self, indent: &str, content: &KdlValue, writer: Writer) -> EmitResult {
+        self.emit_text(indent, content, self.text_whitespace, writer)
+    }
+
+    fn emit_text(
+        &self,
+        indent: &str,
+        content: &KdlValue,
+        whitespace: TextWhitespace,
+        writer: Writer,
+    ) -> EmitResult {
+        let mut text = self.vars.expand_value(content);
+        if whitespace != TextWhitespace::default() {
+            text = whitespace.apply(&text, indent).into();
+        }
         write!(
             writer,
             "{}{}",
             indent,
-            html_escape::encode_text(&self.vars.expand_value(content))
+            compiled::mark_escaped(self.escape_text(&text))
         )?;
         self.write_line(writer)?;
         Ok(())
     }
 
+    /// Emits `content` as-is, without escaping or expanding variables. Its lines are
+    /// dedented, keeping their relative indentation, and then indented by `indent`. Blank
+    /// lines around it are dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use htmeta::HtmlEmitter;
+    /// let emitter = HtmlEmitter::builder().indent(4).build();
+    /// let mut writer = Vec::<u8>::new();
+    /// let content = "\n    if (a) {\n        b();\n    }\n";
+    /// emitter.emit_raw_node("  ", content, &mut writer).unwrap();
+    /// assert_eq!(writer, b"  if (a) {\n      b();\n  }\n");
+    /// ```
+    pub fn emit_raw_node(&self, indent: &str, content: &str, writer: Writer) -> EmitResult {
+        for (i, line) in whitespace::dedent(content).iter().enumerate() {
+            if i > 0 {
+                // Kept even when minifying, as line breaks can matter, like in scripts
+                writeln!(writer)?;
+            }
+            if !line.is_empty() {
+                write!(writer, "{indent}{line}")?;
+            }
+        }
+        self.write_line(writer)?;
+        Ok(())
+    }
+
     /// Emits the corresponding `HTML` into the `writer`. The emitter can be re-used after this.
     ///
+    /// Variables set beforehand through [`Self::vars`] can be used by the document, and are
+    /// cleared afterwards. Warnings and the filename are kept until [`Self::reset`] is called,
+    /// while everything from the builder is never reset.
+    ///
     /// # Examples:
     ///
     /// ```rust
@@ -445,43 +1407,350 @@ impl<'a> HtmlEmitter<'a> {
     /// let mut file = std::fs::File::create("index.html").unwrap();
     /// emitter.emit(&doc, &mut file).unwrap();
     /// ```
-    pub fn emit<'b: 'a>(&'b mut self, document: &'b KdlDocument, writer: Writer<'b>) -> EmitResult {
-        for node in document.nodes() {
-            let name = node.name().value();
-            let indent = self.indent(node);
+    pub fn emit(&mut self, document: &'a KdlDocument, writer: Writer) -> EmitResult {
+        self.new_nonce();
+        self.emit_output(document, writer)
+    }
 
-            // variable node
-            if name.starts_with("$")
-                && let Some(val) = node.get(0)
-            {
-                let value = self.vars.expand_value(val);
-                self.vars.insert(&name[1..], value);
-                continue;
+    /// Creates the nonce of the next document, if [`HtmlEmitterBuilder::csp_nonce`] was set.
+    fn new_nonce(&mut self) {
+        self.nonce = self.nonce_source.as_ref().map(|source| source.0().into());
+    }
+
+    /// Emits `document` into `writer`, running the post-processors, without creating a new
+    /// nonce.
+    fn emit_output(&mut self, document: &'a KdlDocument, writer: Writer) -> EmitResult {
+        if self.post_processors.is_empty() {
+            return self.emit_document(document, writer);
+        }
+        let mut buf = Vec::<u8>::new();
+        self.emit_document(document, &mut buf)?;
+        let mut html = String::from_utf8(buf)?;
+        for processor in &self.post_processors {
+            html = processor.process(html)?;
+        }
+        writer.write_all(html.as_bytes())?;
+        Ok(())
+    }
+
+    fn emit_document(&mut self, document: &'a KdlDocument, writer: Writer) -> EmitResult {
+        self.vars.usage = Default::default();
+        // Plugins are copied when changed, like when templates are defined, so keeping the
+        // originals undoes those changes once the document is done
+        let plugins = self.plugins.clone();
+        let result = self
+            .call_plugins_start(document)
+            .and_then(|_| self.emit_nodes(document.nodes(), writer))
+            .and_then(|_| self.call_plugins_end(writer));
+        self.plugins = plugins;
+        result?;
+        let errors = std::mem::take(&mut self.vars.usage.borrow_mut().errors);
+        for error in errors {
+            self.warn(error);
+        }
+        if self.warn_unused {
+            for key in self.vars.unused() {
+                self.warn(format!("${key}: Variable is set but never used."));
             }
+        }
+        // Allows this instance to be reused
+        self.vars.clear();
+        Ok(())
+    }
 
-            // text/content node
-            if (name == "-" || name == "text")
-                && let Some(content) = node.get(0)
-            {
-                if name == "text" {
-                    eprintln!("`text` nodes are now deprecated. Please use the new syntax.\n")
-                }
-                self.emit_text_node(&indent, content, writer)?;
-                continue;
+    /// Emits `document` into several writers, each with its own indentation, like
+    /// [`HtmlEmitterBuilder::indent`] would set. `None` follows the document's original
+    /// indentation. This is handy for writing both a readable and a minified copy of a page,
+    /// without parsing it twice.
+    ///
+    /// The document is emitted once per output, each starting from the same variables, so
+    /// ones set beforehand through [`Self::vars`] are seen by every output. Warnings are only
+    /// reported once, but plugins run once per output. Every output gets the same
+    /// [nonce](Self::csp_nonce).
+    ///
+    /// # Example
+    /// ```rust
+    /// use htmeta::HtmlEmitter;
+    /// use kdl::KdlDocument;
+    /// let doc: KdlDocument = r#"div { p "Hello" }"#.parse().unwrap();
+    /// let mut emitter = HtmlEmitter::builder().build();
+    /// let (mut pretty, mut minified) = (Vec::<u8>::new(), Vec::<u8>::new());
+    /// emitter
+    ///     .emit_multi(&doc, &mut [(Some(2), &mut pretty), (Some(0), &mut minified)])
+    ///     .unwrap();
+    /// assert_eq!(pretty, b"<div>\n  <p>Hello</p>\n</div>\n");
+    /// assert_eq!(minified, b"<div><p>Hello</p></div>");
+    /// ```
+    pub fn emit_multi(
+        &mut self,
+        document: &'a KdlDocument,
+        outputs: &mut [(Option<Indent>, Writer)],
+    ) -> EmitResult {
+        let (indent, vars, index, previous) =
+            (self.indent, self.vars.clone(), self.index, self.previous);
+        let mut result = Ok(());
+        self.new_nonce();
+        for (output_indent, writer) in outputs {
+            self.indent = *output_indent;
+            self.vars = vars.clone();
+            (self.index, self.previous) = (index, previous);
+            result = self.emit_output(document, *writer);
+            if result.is_err() {
+                break;
             }
+        }
+        self.indent = indent;
+        self.vars.clear();
+        result
+    }
+
+    fn call_plugins_start(&mut self, document: &KdlDocument) -> EmitResult {
+        let info = EmitInfo {
+            document,
+            filename: self.filename.as_deref(),
+            defines: &self.defines,
+        };
+        for plug in &self.plugins {
+            plug.0.on_start(&mut self.vars, &info)?;
+        }
+        Ok(())
+    }
+
+    fn call_plugins_end(&self, mut writer: Writer) -> EmitResult {
+        for plug in &self.plugins {
+            let ctx = PluginContext {
+                indent: "",
+                emitter: self,
+                writer: &mut writer,
+            };
+            plug.0.on_end(ctx)?;
+        }
+        Ok(())
+    }
 
-            // Plugin shenanigans
-            if self.call_plugin(node, &indent, writer)? {
-                continue;
+    /// Tells plugins that the `head` or `body` tag, as `name`, is about to be closed. `self`
+    /// must be the emitter of its children, in `doc`, if it has any.
+    fn call_plugins_boundary(
+        &self,
+        name: &str,
+        doc: Option<&KdlDocument>,
+        mut writer: Writer,
+    ) -> EmitResult {
+        let indent = match doc.and_then(|doc| doc.nodes().last()) {
+            Some(last) => self.indent(last),
+            None => " ".repeat(self.current_level * self.indent.unwrap_or(0)),
+        };
+        for plug in &self.plugins {
+            let ctx = PluginContext {
+                indent: &indent,
+                emitter: self,
+                writer: &mut writer,
+            };
+            match name {
+                "head" => plug.0.on_head_end(ctx)?,
+                _ => plug.0.on_body_end(ctx)?,
             }
+        }
+        Ok(())
+    }
 
-            // Compound node, AKA, normal HTML tag.
-            self.emit_tag(node, name, &indent, writer)?
+    /// Emits `@when` and `@unless` blocks. Their children are emitted in the current scope,
+    /// so variables set inside them are visible afterwards.
+    fn emit_conditional(&mut self, node: &'a KdlNode, negate: bool, writer: Writer) -> EmitResult {
+        let name = node.name().value();
+        let flag = node.get(0).ok_or_else(|| Error::MissingArgument {
+            node: name.into(),
+            argument: "flag".into(),
+        })?;
+        if self.is_defined(&self.vars.expand_known(name, flag)?) == negate {
+            return Ok(());
+        }
+        if let Some(children) = node.children() {
+            self.emit_nodes(children.nodes(), writer)?;
+        }
+        Ok(())
+    }
+
+    /// Checks an `@assert "${ $count > 0 }" msg="..."` node, failing with
+    /// [`Error::AssertionFailed`] if its condition is empty, `false` or `0`.
+    fn check_assert(&self, node: &KdlNode) -> EmitResult {
+        let condition = node.get(0).ok_or_else(|| Error::MissingArgument {
+            node: "@assert".into(),
+            argument: "condition".into(),
+        })?;
+        if expr::is_truthy(&self.vars.expand_known("@assert", condition)?) {
+            return Ok(());
+        }
+        let message = match node.get("msg") {
+            Some(message) => self.vars.expand_value(message).into_owned(),
+            None => format!("`{}` is false.", condition.as_string().unwrap_or_default()),
+        };
+        Err(Error::AssertionFailed { message })
+    }
+
+    /// Emits a `@while "${ $i < 10 }" max=100` node's children until its condition is false,
+    /// in the current scope so they can change the variables it uses. Fails with
+    /// [`Error::TooManyIterations`] if it is still true after `max` iterations.
+    fn emit_while(&mut self, node: &'a KdlNode, writer: Writer) -> EmitResult {
+        let condition = node.get(0).ok_or_else(|| Error::MissingArgument {
+            node: "@while".into(),
+            argument: "condition".into(),
+        })?;
+        let max = node.get("max").ok_or_else(|| Error::MissingProperty {
+            node: "@while".into(),
+            property: "max".into(),
+        })?;
+        let max = self
+            .vars
+            .expand_known("@while", max)?
+            .parse()
+            .map_err(|_| Error::InvalidProperty {
+                node: "@while".into(),
+                property: "max".into(),
+                expected: "a whole number".into(),
+            })?;
+        let mut iterations = 0;
+        while expr::is_truthy(&self.vars.expand_known("@while", condition)?) {
+            if iterations == max {
+                return Err(Error::TooManyIterations { max });
+            }
+            if let Some(children) = node.children() {
+                self.emit_nodes(children.nodes(), writer)?;
+            }
+            iterations += 1;
         }
-        // Allows this instance to be reused
-        self.vars.clear();
         Ok(())
     }
+
+    /// Emits a single `node` and its children into the `writer`, without requiring a whole
+    /// [`KdlDocument`]. This is useful for rendering partials, like a single component in
+    /// response to an `htmx` request.
+    ///
+    /// Unlike [`Self::emit`], variables are kept afterwards, so you can set them up
+    /// beforehand through [`Self::vars`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use htmeta::HtmlEmitter;
+    /// use htmeta::kdl::KdlNode;
+    ///
+    /// let mut emitter = HtmlEmitter::builder().minify().build();
+    /// let node = r#"li class="item" "$name""#.parse::<KdlNode>().unwrap();
+    /// emitter.vars.insert("name", "Milk".into());
+    /// let mut result = Vec::<u8>::new();
+    /// emitter.emit_fragment(&node, &mut result).unwrap();
+    /// assert_eq!(result, br#"<li class="item">Milk</li>"#);
+    /// ```
+    pub fn emit_fragment(&mut self, node: &'a KdlNode, writer: Writer) -> EmitResult {
+        self.emit_nodes(std::slice::from_ref(node), writer)
+    }
+
+    /// Emits every node in `nodes` into the `writer`, in order. Check out
+    /// [`Self::emit_fragment`] for more information.
+    ///
+    /// Fails with [`Error::TooDeep`] if nested deeper than the maximum depth.
+    pub fn emit_nodes(&mut self, nodes: &'a [KdlNode], writer: Writer) -> EmitResult {
+        if self.depth >= self.max_depth {
+            return Err(Error::TooDeep {
+                max_depth: self.max_depth,
+                path: Vec::new(),
+            });
+        }
+        self.depth += 1;
+        let mut result = Ok(());
+        for (index, node) in nodes.iter().enumerate() {
+            let name = node.name().value();
+            self.index = index;
+            result = self.emit_node(node, writer);
+            if result.is_ok()
+                && let Some(name) = self.vars.usage.borrow_mut().missing.take()
+            {
+                result = Err(Error::MissingVariable { name });
+            }
+            if let Err(Error::TooDeep { path, .. }) = &mut result {
+                path.insert(0, name.into());
+            }
+            if result.is_err() {
+                break;
+            }
+            // Conditional blocks update this with their own children
+            if !name.starts_with('$') && !["@when", "@unless", "@assert", "@while"].contains(&name)
+            {
+                self.previous = Some(name);
+            }
+        }
+        self.depth -= 1;
+        result
+    }
+
+    fn emit_node(&mut self, node: &'a KdlNode, writer: Writer) -> EmitResult {
+        let name = node.name().value();
+        let indent = self.indent(node);
+
+        // variable node
+        if name.starts_with("$")
+            && let Some(val) = node.get(0)
+        {
+            let value = self.vars.expand_value(val);
+            self.vars.insert_from(&name[1..], val, value);
+            self.vars.mark_set(&name[1..]);
+            return Ok(());
+        }
+
+        // text/content node
+        if (name == "-" || name == "text")
+            && let Some(content) = node.get(0)
+        {
+            if name == "text" {
+                eprintln!("`text` nodes are now deprecated. Please use the new syntax.\n")
+            }
+            let whitespace = self.text_whitespace.for_node(node);
+            self.emit_text(&indent, content, whitespace, writer)?;
+            return Ok(());
+        }
+
+        // raw block node
+        if name == "_" {
+            let content = match node.get(0) {
+                Some(KdlValue::String(content)) => content.clone(),
+                Some(content) => content.to_string(),
+                None => {
+                    return Err(Error::MissingArgument {
+                        node: name.into(),
+                        argument: "content".into(),
+                    })
+                }
+            };
+            return self.emit_raw_node(&indent, &content, writer);
+        }
+
+        // conditional compilation node
+        if name == "@when" || name == "@unless" {
+            self.emit_conditional(node, name == "@unless", writer)?;
+            return Ok(());
+        }
+
+        if name == "@assert" {
+            return self.check_assert(node);
+        }
+
+        if name == "@while" {
+            return self.emit_while(node, writer);
+        }
+
+        // Plugin shenanigans
+        if self.call_plugin(node, &indent, writer)? {
+            return Ok(());
+        }
+
+        // Commands are only meaningful to plugins
+        if name.starts_with('@') {
+            return Err(Error::UnknownCommand { name: name.into() });
+        }
+
+        // Compound node, AKA, normal HTML tag.
+        self.emit_tag(node, name, &indent, writer)
+    }
 }
 
 #[doc(hidden)]
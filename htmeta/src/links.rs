@@ -0,0 +1,79 @@
+//! Normalization of internal links, so every page links to others in the same way.
+
+/// Attributes that contain links to other pages.
+pub(crate) const LINK_ATTRIBUTES: &[&str] = &["href", "action"];
+
+/// What to do with trailing slashes in internal links to directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingSlash {
+    /// Leaves links as the author wrote them.
+    #[default]
+    Keep,
+    /// Ensures links end with a slash, like `/blog/`.
+    Add,
+    /// Ensures links don't end with a slash, like `/blog`.
+    Strip,
+}
+
+/// How internal links in `href` and `action` attributes are rewritten. Links with a scheme,
+/// like `https://` or `mailto:`, are never touched.
+///
+/// The default leaves every link untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LinkNormalization {
+    /// What to do with trailing slashes. Links to files, like `style.css`, are left alone.
+    pub trailing_slash: TrailingSlash,
+    /// Rewrites links to `index.html` files into links to their directory, so that
+    /// `/blog/index.html` becomes `/blog/`.
+    pub directory_index: bool,
+}
+
+impl LinkNormalization {
+    /// Returns `true` if links are left untouched.
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the normalized form of `link`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use htmeta::{LinkNormalization, TrailingSlash};
+    /// let links = LinkNormalization {
+    ///     trailing_slash: TrailingSlash::Add,
+    ///     directory_index: true,
+    /// };
+    /// assert_eq!(links.normalize("/blog"), "/blog/");
+    /// assert_eq!(links.normalize("/blog/index.html#top"), "/blog/#top");
+    /// assert_eq!(links.normalize("https://example.com/blog"), "https://example.com/blog");
+    /// ```
+    pub fn normalize(&self, link: &str) -> String {
+        re!(EXTERNAL, r"^(?:[a-zA-Z][a-zA-Z0-9+.-]*:|//)");
+        if EXTERNAL.is_match(link) {
+            return link.into();
+        }
+        let (path, suffix) = link.split_at(link.find(['?', '#']).unwrap_or(link.len()));
+        let mut path = path.to_string();
+        if self.directory_index && (path == "index.html" || path.ends_with("/index.html")) {
+            path.truncate(path.len() - "index.html".len());
+            if path.is_empty() {
+                path.push_str("./");
+            }
+        }
+        let last_segment = path.rsplit('/').next().unwrap_or_default();
+        match self.trailing_slash {
+            TrailingSlash::Keep => {}
+            TrailingSlash::Add => {
+                if !path.is_empty() && !path.ends_with('/') && !last_segment.contains('.') {
+                    path.push('/');
+                }
+            }
+            TrailingSlash::Strip => {
+                if path.len() > 1 && path.ends_with('/') && path != "./" {
+                    path.pop();
+                }
+            }
+        }
+        path + suffix
+    }
+}
@@ -5,6 +5,10 @@ auto_html_test!(basic_test);
 auto_html_test!(basic_test2);
 auto_html_test!(basic_var);
 auto_html_test!(var_scopes);
+auto_html_test!(number_filters);
+auto_html_test!(expressions);
+auto_html_test!(raw_block);
+auto_html_test!(while_loop);
 
 fn minified() -> HtmlEmitterBuilder {
     let mut builder = HtmlEmitter::builder();
@@ -16,6 +20,181 @@ auto_html_test!(minified_basic, minified());
 auto_html_test!(minified_var_scopes, minified());
 
 auto_html_test_fail!(fail_mixed_text);
+auto_html_test_fail!(fail_void_children);
+auto_html_test_fail!(fail_unknown_command);
+auto_html_test_fail!(fail_assert);
+auto_html_test_fail!(fail_endless_loop);
+
+fn with_max_depth() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.max_depth(3);
+    builder
+}
+
+auto_html_test_fail!(fail_too_deep, with_max_depth());
+
+fn with_entities() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.preserve_entities();
+    builder
+}
+
+auto_html_test!(preserved_entities, with_entities());
+
+fn with_link_normalization() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.normalize_links(LinkNormalization {
+        trailing_slash: TrailingSlash::Add,
+        directory_index: true,
+    });
+    builder
+}
+
+auto_html_test!(normalized_links, with_link_normalization());
+
+fn with_text_whitespace() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.text_whitespace(TextWhitespace {
+        trim: true,
+        dedent: true,
+        collapse: false,
+    });
+    builder
+}
+
+auto_html_test!(text_whitespace, with_text_whitespace());
+
+fn with_strict_vars() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.on_missing_var(|name| match name {
+        "price" => MissingVarAction::KeepLiteral,
+        _ => MissingVarAction::Error,
+    });
+    builder
+}
+
+auto_html_test_fail!(fail_missing_var, with_strict_vars());
+
+fn with_sorted_attributes() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.sort_attributes();
+    builder
+}
+
+auto_html_test!(sorted_attributes, with_sorted_attributes());
+
+/// Adds a comment to the end of the page.
+struct Signature;
+
+impl PostProcessor for Signature {
+    fn process(&self, html: String) -> EmitResult<String> {
+        Ok(html + "<!-- Made with htmeta -->\n")
+    }
+}
+
+fn with_post_processor() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.add_post_processor(Signature);
+    builder
+}
+
+auto_html_test!(post_processed, with_post_processor());
+
+#[test]
+fn fn_plugin() {
+    let mut builder = minified();
+    builder.add_fn_plugin(
+        |node| node.name().value() == "@badge",
+        |node, context| {
+            let Some(label) = node.get(0) else {
+                return Err("@badge: Missing its label!".into());
+            };
+            let label = context.emitter.vars.expand_value(label);
+            Ok(write!(
+                context.writer,
+                r#"<span class="badge">{label}</span>"#
+            )?)
+        },
+    );
+    let emit = |source: &str| {
+        let doc: KdlDocument = source.parse().unwrap();
+        let mut result = Vec::<u8>::new();
+        builder
+            .build()
+            .emit(&doc, &mut result)
+            .map(|_| String::from_utf8(result).unwrap())
+    };
+    // Nodes it doesn't match are emitted as usual
+    htmeta_auto_test::assert_eq!(
+        emit(r#"$tag "New"; p { @badge "$tag"; span "Post" }"#),
+        Ok(r#"<p><span class="badge">New</span><span>Post</span></p>"#.into())
+    );
+    htmeta_auto_test::assert_eq!(
+        emit("@badge"),
+        Err(Error::UserError {
+            message: "@badge: Missing its label!".into()
+        })
+    );
+}
+
+#[test]
+fn post_processed_invalid_utf8() {
+    let mut builder = with_post_processor();
+    builder.add_fn_plugin(
+        |node| node.name().value() == "@bytes",
+        |_, context| Ok(context.writer.write_all(&[0xff])?),
+    );
+    let doc: KdlDocument = "p { @bytes }".parse().unwrap();
+    let error = builder.build().emit(&doc, &mut Vec::new()).unwrap_err();
+    assert!(matches!(error, Error::InvalidUtf8(_)));
+}
+
+fn with_duplicates(policy: DuplicateAttributes) -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.duplicate_attributes(policy);
+    builder
+}
+
+auto_html_test!(
+    merged_attributes,
+    with_duplicates(DuplicateAttributes::Merge)
+);
+auto_html_test_fail!(
+    fail_duplicate_attribute,
+    with_duplicates(DuplicateAttributes::Error)
+);
+auto_html_test_fail!(
+    fail_duplicate_expanded_attribute,
+    with_duplicates(DuplicateAttributes::Error)
+);
+auto_html_test!(escaped_attributes);
+auto_html_test_fail!(fail_attribute_argument);
+auto_html_test_fail!(fail_attribute_quote);
+
+fn with_csp() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.csp_nonce(|| "bm90LXJhbmRvbQ==".into()).csp_meta();
+    builder
+}
+
+auto_html_test!(csp_nonce, with_csp());
+
+fn with_defines() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.define("draft");
+    builder
+}
+
+auto_html_test!(conditional_flags, with_defines());
+
+fn with_env() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.env_vars();
+    builder
+}
+
+// Cargo sets `CARGO_PKG_NAME` when running tests
+auto_html_test!(env_vars, with_env());
 
 #[derive(Clone)]
 struct ShouterPlugin;
@@ -37,3 +216,573 @@ fn with_plugin() -> HtmlEmitterBuilder {
 }
 
 auto_html_test!(shouter_basic, with_plugin());
+
+/// Separates consecutive `@join` nodes with commas.
+#[derive(Clone)]
+struct JoinPlugin;
+
+impl IPlugin for JoinPlugin {
+    fn emit_node(&self, node: &KdlNode, context: PluginContext) -> EmitResult<EmitStatus> {
+        if node.name().value() != "@join" {
+            return Ok(EmitStatus::Skip);
+        }
+        let emitter = context.emitter;
+        let separator = match emitter.previous_sibling() {
+            Some("@join") => ", ",
+            _ => "",
+        };
+        write!(
+            context.writer,
+            r#"{}{separator}<span data-index="{}">{}</span>"#,
+            context.indent,
+            emitter.index(),
+            emitter.vars.expand_value(node.get(0).unwrap())
+        )?;
+        emitter.write_line(context.writer)?;
+        Ok(EmitStatus::Emmited)
+    }
+}
+
+fn with_join_plugin() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.add_plugin(JoinPlugin);
+    builder
+}
+
+auto_html_test!(plugin_siblings, with_join_plugin());
+
+/// Defines `$greeting` before emitting.
+#[derive(Clone)]
+struct GreeterPlugin;
+
+impl IPlugin for GreeterPlugin {
+    fn emit_node(&self, _: &KdlNode, _: PluginContext) -> EmitResult<EmitStatus> {
+        Ok(EmitStatus::Skip)
+    }
+
+    fn on_start(&self, vars: &mut Vars, info: &EmitInfo) -> EmitResult {
+        let nodes = info.document.nodes().len();
+        vars.insert("greeting", format!("Hello, {nodes} nodes").into());
+        Ok(())
+    }
+}
+
+fn with_greeter_plugin() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.add_plugin(GreeterPlugin);
+    builder
+}
+
+auto_html_test!(plugin_vars, with_greeter_plugin());
+
+/// Adds a stylesheet to the `head` and a script to the `body`.
+#[derive(Clone)]
+struct AssetsPlugin;
+
+impl IPlugin for AssetsPlugin {
+    fn emit_node(&self, _: &KdlNode, _: PluginContext) -> EmitResult<EmitStatus> {
+        Ok(EmitStatus::Skip)
+    }
+
+    fn on_head_end(&self, context: PluginContext) -> EmitResult {
+        write!(
+            context.writer,
+            r#"{}<link rel="stylesheet" href="style.css">"#,
+            context.indent
+        )?;
+        context.emitter.write_line(context.writer)
+    }
+
+    fn on_body_end(&self, context: PluginContext) -> EmitResult {
+        write!(
+            context.writer,
+            r#"{}<script src="app.js"></script>"#,
+            context.indent
+        )?;
+        context.emitter.write_line(context.writer)
+    }
+}
+
+fn with_assets_plugin() -> HtmlEmitterBuilder {
+    let mut builder = HtmlEmitter::builder();
+    builder.add_plugin(AssetsPlugin);
+    builder
+}
+
+auto_html_test!(plugin_boundaries, with_assets_plugin());
+auto_html_test!(plugin_boundaries_empty, with_assets_plugin());
+
+#[test]
+fn emit_fragment() {
+    let doc: KdlDocument = r#"
+        ul {
+            li "$name"
+            li "Eggs"
+        }"#
+    .parse()
+    .unwrap();
+    let item = &doc.nodes()[0].children().unwrap().nodes()[0];
+
+    let mut emitter = minified().build();
+    emitter.vars.insert("name", "Milk".into());
+    let mut result = Vec::<u8>::new();
+    emitter.emit_fragment(item, &mut result).unwrap();
+    htmeta_auto_test::assert_eq!(String::from_utf8(result).unwrap(), "<li>Milk</li>");
+}
+
+#[test]
+fn emitter_reuse() {
+    let first: KdlDocument = r#"p "First""#.parse().unwrap();
+    let second: KdlDocument = r#"p "Second""#.parse().unwrap();
+
+    let mut emitter = minified().build();
+    let mut result = Vec::<u8>::new();
+    emitter.emit(&first, &mut result).unwrap();
+    emitter.emit(&second, &mut result).unwrap();
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(result).unwrap(),
+        "<p>First</p><p>Second</p>"
+    );
+}
+
+#[test]
+fn kdl_v1_compat() {
+    let v1 = r##"
+        // Comments with true and r"raw strings" are left alone
+        input type="checkbox" checked=true value=null
+        p r#"Some "quoted" text"#
+        p "Multi
+line \/ text"
+    "##;
+    let v2 = r#"
+        input type="checkbox" checked=#true value=#null
+        p "Some \"quoted\" text"
+        p "Multi\nline / text"
+    "#;
+    let emit = |doc: &KdlDocument| {
+        let mut result = Vec::<u8>::new();
+        minified().build().emit(doc, &mut result).unwrap();
+        String::from_utf8(result).unwrap()
+    };
+    htmeta_auto_test::assert_eq!(
+        emit(&parse_document(v1).unwrap()),
+        emit(&v2.parse().unwrap())
+    );
+}
+
+#[test]
+fn unused_vars() {
+    let doc: KdlDocument = r#"
+        $title "Hello"
+        $titel "Typo"
+        $unused "Not a typo, still unused"
+        h1 "$title"
+    "#
+    .parse()
+    .unwrap();
+    let mut builder = minified();
+    builder.warn_unused();
+    let mut emitter = builder.build();
+    emitter.emit(&doc, &mut Vec::<u8>::new()).unwrap();
+    let warnings: Vec<_> = emitter
+        .take_warnings()
+        .into_iter()
+        .map(|warning| warning.message)
+        .collect();
+    htmeta_auto_test::assert_eq!(
+        warnings,
+        vec![
+            "$titel: Variable is set but never used.".to_string(),
+            "$unused: Variable is set but never used.".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn unused_vars_only_with_warn_unused() {
+    let doc: KdlDocument = r#"$title "Hi"; $unused "Bye"; h1 "$title""#.parse().unwrap();
+    let warnings = |builder: &HtmlEmitterBuilder| {
+        let mut emitter = builder.build();
+        emitter.emit(&doc, &mut Vec::<u8>::new()).unwrap();
+        emitter
+            .take_warnings()
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect::<Vec<_>>()
+    };
+    let mut builder = minified();
+    assert!(warnings(&builder).is_empty());
+    builder.warn_unused();
+    htmeta_auto_test::assert_eq!(
+        warnings(&builder),
+        vec!["$unused: Variable is set but never used.".to_string()]
+    );
+}
+
+#[test]
+fn expression_errors() {
+    let doc: KdlDocument = r#"
+        $name "Milk"
+        p "${ $name * 2 }"
+        p "${ $missing + 1 }"
+        p "${ 1 / 0 }"
+        script "`Hi, ${name}`"
+    "#
+    .parse()
+    .unwrap();
+    let mut emitter = minified().build();
+    let mut result = Vec::<u8>::new();
+    emitter.emit(&doc, &mut result).unwrap();
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(result).unwrap(),
+        "<p></p><p></p><p></p><script>`Hi, ${name}`</script>"
+    );
+    let warnings: Vec<_> = emitter
+        .take_warnings()
+        .into_iter()
+        .map(|warning| warning.message)
+        .collect();
+    htmeta_auto_test::assert_eq!(
+        warnings,
+        vec![
+            "${ $name * 2 }: `*` only works with numbers.".to_string(),
+            "${ $missing + 1 }: `$missing` is not set.".to_string(),
+            "${ 1 / 0 }: Division by zero.".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn invalid_attribute_names() {
+    let doc: KdlDocument = r#"
+        $attr "onmouseover=alert(1) x"
+        $name "data-id"
+        div $name="1" "$attr"="2" "aria-label"="Hi"
+    "#
+    .parse()
+    .unwrap();
+    let mut builder = minified();
+    htmeta_auto_test::assert_eq!(
+        builder.build().emit(&doc, &mut Vec::<u8>::new()),
+        Err(Error::InvalidAttributeName {
+            tag: "div".into(),
+            attribute: "onmouseover=alert(1) x".into()
+        })
+    );
+    builder.invalid_attributes(InvalidAttributes::Skip);
+    let mut emitter = builder.build();
+    let mut result = Vec::<u8>::new();
+    emitter.emit(&doc, &mut result).unwrap();
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(result).unwrap(),
+        r#"<div data-id="1" aria-label="Hi"></div>"#
+    );
+    htmeta_auto_test::assert_eq!(emitter.take_warnings().len(), 1);
+}
+
+#[test]
+fn untrusted_vars() {
+    let doc: KdlDocument = r#"
+        $greeting "Hi, $name"
+        p "$greeting"
+        p title="$name" {
+            - "$name"
+        }
+    "#
+    .parse()
+    .unwrap();
+    let mut emitter = minified().build();
+    emitter.vars.insert_untrusted("name", "<b>&".into());
+    let mut result = Vec::<u8>::new();
+    emitter.emit(&doc, &mut result).unwrap();
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(result).unwrap(),
+        r#"<p>Hi, &lt;b&gt;&amp;</p><p title="&lt;b&gt;&amp;">&lt;b&gt;&amp;</p>"#
+    );
+}
+
+#[test]
+fn emit_multi_keeps_vars() {
+    let doc: KdlDocument = r#"div { p "Hello, $name!" }"#.parse().unwrap();
+    let mut emitter = HtmlEmitter::builder().build();
+    emitter.vars.insert("name", "World".into());
+    let (mut pretty, mut minified) = (Vec::<u8>::new(), Vec::<u8>::new());
+    emitter
+        .emit_multi(
+            &doc,
+            &mut [(Some(2), &mut pretty), (Some(0), &mut minified)],
+        )
+        .unwrap();
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(pretty).unwrap(),
+        "<div>\n  <p>Hello, World!</p>\n</div>\n"
+    );
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(minified).unwrap(),
+        "<div><p>Hello, World!</p></div>"
+    );
+}
+
+#[test]
+fn csp_nonce_per_document() {
+    let doc: KdlDocument = r#"script src="app.js""#.parse().unwrap();
+    let count = Rc::new(std::cell::Cell::new(0));
+    let counter = count.clone();
+    let mut builder = minified();
+    builder.csp_nonce(move || {
+        counter.set(counter.get() + 1);
+        counter.get().to_string()
+    });
+    let mut emitter = builder.build();
+    emitter.emit(&doc, &mut Vec::<u8>::new()).unwrap();
+    htmeta_auto_test::assert_eq!(emitter.csp_nonce(), Some("1"));
+    emitter.emit(&doc, &mut Vec::<u8>::new()).unwrap();
+    htmeta_auto_test::assert_eq!(emitter.csp_nonce(), Some("2"));
+
+    let (mut first, mut second) = (Vec::<u8>::new(), Vec::<u8>::new());
+    emitter
+        .emit_multi(&doc, &mut [(Some(2), &mut first), (Some(0), &mut second)])
+        .unwrap();
+    htmeta_auto_test::assert_eq!(count.get(), 3);
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(second).unwrap(),
+        r#"<script src="app.js" nonce="3"></script>"#
+    );
+}
+
+#[test]
+fn dedent_mixed_whitespace() {
+    let whitespace = TextWhitespace {
+        dedent: true,
+        ..Default::default()
+    };
+    // A no-break space and an ideographic space, which are longer than a byte
+    htmeta_auto_test::assert_eq!(
+        whitespace.apply("\u{a0}  Hello\n\u{3000}  world\n  !", ""),
+        "\u{a0}  Hello\n\u{3000}  world\n  !"
+    );
+    htmeta_auto_test::assert_eq!(
+        whitespace.apply("\u{3000} Hello\n\u{3000}   world", ""),
+        "Hello\n  world"
+    );
+}
+
+#[test]
+fn emitter_reset() {
+    let doc: KdlDocument = r#"$unused "Hi""#.parse().unwrap();
+    let mut builder = minified();
+    builder.warn_unused();
+    let mut emitter = builder.build();
+    emitter.reset_for("pages/index.kdl");
+    emitter.emit(&doc, &mut Vec::<u8>::new()).unwrap();
+    // Warnings and the filename outlive `emit`
+    assert!(emitter.filename() == Some(std::path::Path::new("pages/index.kdl")));
+    htmeta_auto_test::assert_eq!(emitter.take_warnings().len(), 1);
+
+    emitter.vars.insert("name", "Milk".into());
+    emitter.warn("Leftover");
+    emitter.reset();
+    assert!(emitter.filename().is_none());
+    assert!(emitter.vars.get("name").is_none());
+    assert!(emitter.take_warnings().is_empty());
+}
+
+#[test]
+fn compiled_doc() {
+    let doc: KdlDocument = r#"
+        $greeting "Hello, $name"
+        h1 "$greeting!"
+        p {
+            - "$bio"
+        }
+        p "$price|fixed:2"
+    "#
+    .parse()
+    .unwrap();
+    let compiled = CompiledDoc::compile(&minified(), &doc).unwrap();
+
+    let render = |name: &str, bio: &str, price: &str| {
+        let mut vars = Vars::default();
+        vars.extend([
+            ("name", name.into()),
+            ("bio", bio.into()),
+            ("price", price.into()),
+        ]);
+        let mut result = Vec::<u8>::new();
+        compiled.emit(&vars, &mut result).unwrap();
+        String::from_utf8(result).unwrap()
+    };
+    htmeta_auto_test::assert_eq!(
+        render("World", "<3", "3"),
+        "<h1>Hello, World!</h1><p>&lt;3</p><p>3.00</p>"
+    );
+    htmeta_auto_test::assert_eq!(
+        render("htmeta", "Compiled", "0.5"),
+        "<h1>Hello, htmeta!</h1><p>Compiled</p><p>0.50</p>"
+    );
+}
+
+#[test]
+fn compiled_doc_attributes() {
+    let doc: KdlDocument = r#"a href="$url" title="$title" "Link""#.parse().unwrap();
+    let mut builder = minified();
+    builder.preserve_entities();
+    let compiled = CompiledDoc::compile(&builder, &doc).unwrap();
+
+    let mut vars = Vars::default();
+    vars.extend([
+        ("url", r#""><script>"#.into()),
+        ("title", "&copy; 2024".into()),
+    ]);
+    let mut result = Vec::<u8>::new();
+    compiled.emit(&vars, &mut result).unwrap();
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(result).unwrap(),
+        r#"<a href="&quot;&gt;&lt;script&gt;" title="&copy; 2024">Link</a>"#
+    );
+}
+
+#[test]
+fn compiled_doc_deferred_conditions() {
+    let compile = |builder: &HtmlEmitterBuilder, source: &str| {
+        CompiledDoc::compile(builder, &source.parse().unwrap()).unwrap_err()
+    };
+    htmeta_auto_test::assert_eq!(
+        compile(&minified(), r#"@when "$x" { p "Shown" }"#),
+        Error::DeferredVariable {
+            node: "@when".into()
+        }
+    );
+    htmeta_auto_test::assert_eq!(
+        compile(&minified(), r#"@assert "${ $count > 0 }""#),
+        Error::DeferredVariable {
+            node: "@assert".into()
+        }
+    );
+    htmeta_auto_test::assert_eq!(
+        compile(&with_link_normalization(), r#"a href="$x" "Link""#),
+        Error::DeferredVariable { node: "a".into() }
+    );
+}
+
+#[test]
+fn document_paths() {
+    let split = |path: &str| {
+        let file = parse_document(&format!(r#"@document "{path}" {{ p "Hi" }}"#)).unwrap();
+        split_documents(&file).map(|documents| documents[0].0.clone())
+    };
+    htmeta_auto_test::assert_eq!(split("errors/404.html"), Ok("errors/404.html".into()));
+    for path in ["/etc/passwd.html", "../404.html", "errors/../../404.html"] {
+        htmeta_auto_test::assert_eq!(
+            split(path),
+            Err(Error::InvalidArgument {
+                node: "@document".into(),
+                argument: "path".into(),
+                expected: "a relative path without `..`".into(),
+            })
+        );
+    }
+}
+
+#[test]
+fn css_rules() {
+    let css = "@import url(a.css); p { color: red } @media print { p { margin: 0 } }";
+    let rules: Vec<_> = css::rules(css).iter().map(ToString::to_string).collect();
+    htmeta_auto_test::assert_eq!(
+        rules,
+        [
+            "@import url(a.css);",
+            "p{color: red}",
+            "@media print{p { margin: 0 }}"
+        ]
+    );
+}
+
+#[test]
+fn css_selectors() {
+    let page = css::elements(r#"<P class="Note big" id="intro">"#);
+    let matches = |selector| css::Selector::parse(selector).unwrap().matches(&page[0]);
+    assert!(matches("p.Note#intro") && matches("*.big") && matches("P"));
+    assert!(!matches("p.note") && !matches("#Intro") && !matches("p.Note.small"));
+    assert!(css::Selector::parse("main > p").is_none());
+}
+
+#[test]
+fn email_page() {
+    let html = concat!(
+        "<html><head><style>p { color: red } .note { color: blue; margin: 0 } ",
+        "#intro { font-weight: bold } a:hover { color: green } ",
+        "@media (max-width: 600px) { p { font-size: 12px } }</style>",
+        r#"<script>alert("hi")</script></head><body><section>"#,
+        r#"<p id="intro" class="note" style="padding: 1px">Hi</p><table><tr><td>"#,
+        "<input disabled></td></tr></table></section></body></html>"
+    );
+    htmeta_auto_test::assert_eq!(
+        Email.process(html.into()).unwrap(),
+        concat!(
+            "<html><head><style>a:hover{color: green}@media (max-width: 600px)",
+            "{p { font-size: 12px }}</style></head><body><div>",
+            r#"<p id="intro" class="note" style="color: blue; margin: 0; "#,
+            r#"font-weight: bold; padding: 1px">Hi</p><table role="presentation" "#,
+            r#"border="0" cellpadding="0" cellspacing="0"><tr><td><input disabled>"#,
+            "</td></tr></table></div></body></html>"
+        )
+    );
+}
+
+#[test]
+fn email_keeps_at_rules() {
+    let html = concat!(
+        "<html><head><style>@import url(fonts.css); @charset \"utf-8\";",
+        "p { color: red }</style></head><body><p>Hi</p></body></html>"
+    );
+    htmeta_auto_test::assert_eq!(
+        Email.process(html.into()).unwrap(),
+        concat!(
+            "<html><head><style>@import url(fonts.css);@charset \"utf-8\";</style></head>",
+            r#"<body><p style="color: red">Hi</p></body></html>"#
+        )
+    );
+}
+
+#[test]
+fn email_mode() {
+    let doc: KdlDocument = r#"
+        html {
+            head { style { _ "p { color: red }" } }
+            body { section { p "Hi" } }
+        }
+    "#
+    .parse()
+    .unwrap();
+    let mut builder = minified();
+    builder.mode(OutputMode::Email);
+    let mut result = Vec::<u8>::new();
+    builder.build().emit(&doc, &mut result).unwrap();
+    htmeta_auto_test::assert_eq!(
+        String::from_utf8(result).unwrap(),
+        r#"<html><head></head><body><div><p style="color: red">Hi</p></div></body></html>"#
+    );
+}
+
+#[test]
+fn kdl_node_ext() {
+    use kdl_ext::KdlNodeExt;
+
+    let node: KdlNode = r#"@card "first" title="Hi" "second" id=1"#.parse().unwrap();
+    htmeta_auto_test::assert_eq!(node.command_name(), Some("card"));
+    let args: Vec<_> = node.args().collect();
+    htmeta_auto_test::assert_eq!(args, [&KdlValue::from("first"), &KdlValue::from("second")]);
+    let entries: Vec<_> = node.keyed_entries().collect();
+    htmeta_auto_test::assert_eq!(
+        entries,
+        [("title", &KdlValue::from("Hi")), ("id", &KdlValue::from(1))]
+    );
+    // Its last entry is a property
+    htmeta_auto_test::assert_eq!(node.inline_text(), None);
+
+    let node: KdlNode = r#"h1 class="big" "Title""#.parse().unwrap();
+    htmeta_auto_test::assert_eq!(node.command_name(), None);
+    htmeta_auto_test::assert_eq!(node.inline_text(), Some(&KdlValue::from("Title")));
+    htmeta_auto_test::assert_eq!(KdlNode::new("br").inline_text(), None);
+}
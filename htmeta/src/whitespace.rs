@@ -0,0 +1,111 @@
+//! Cleaning up the whitespace of text nodes, which keep whatever the KDL string had.
+
+use kdl::{KdlNode, KdlValue};
+
+/// How the whitespace of text nodes is cleaned up before they are emitted. Each option
+/// can also be set for a single `-` node through a property of the same name, like
+/// `- "text" collapse=#true`, which overrides the emitter's.
+///
+/// The default keeps text as written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextWhitespace {
+    /// Removes whitespace at the start and end of the text.
+    pub trim: bool,
+    /// Removes the leading whitespace that every line of the text shares, and the blank
+    /// lines around it. Lines are then indented like the text node itself.
+    pub dedent: bool,
+    /// Turns every run of whitespace, including line breaks, into a single space.
+    pub collapse: bool,
+}
+
+impl TextWhitespace {
+    /// Returns the options for the text node `node`, overridden by its properties.
+    pub fn for_node(mut self, node: &KdlNode) -> Self {
+        for (option, key) in [
+            (&mut self.trim, "trim"),
+            (&mut self.dedent, "dedent"),
+            (&mut self.collapse, "collapse"),
+        ] {
+            if let Some(value) = node.get(key).and_then(KdlValue::as_bool) {
+                *option = value;
+            }
+        }
+        self
+    }
+
+    /// Returns `text` cleaned up, with lines after the first one indented by `indent`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use htmeta::TextWhitespace;
+    /// let whitespace = TextWhitespace {
+    ///     dedent: true,
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(whitespace.apply("\n    Hello\n      world\n", "  "), "Hello\n    world");
+    /// let whitespace = TextWhitespace {
+    ///     trim: true,
+    ///     collapse: true,
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(whitespace.apply(" Hello\n   world ", ""), "Hello world");
+    /// ```
+    pub fn apply(&self, text: &str, indent: &str) -> String {
+        let mut text = match self.dedent {
+            true => dedent(text)
+                .iter()
+                .enumerate()
+                .map(|(i, line)| match i == 0 || line.is_empty() {
+                    true => line.to_string(),
+                    false => format!("{indent}{line}"),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            false => text.to_string(),
+        };
+        if self.collapse {
+            re!(WHITESPACE, r"\s+");
+            text = WHITESPACE.replace_all(&text, " ").into_owned();
+        }
+        if self.trim {
+            text = text.trim().to_string();
+        }
+        text
+    }
+}
+
+/// Returns the lines of `text` without the leading whitespace they all share, nor the
+/// blank lines around them. Blank lines in between become empty.
+pub(crate) fn dedent(text: &str) -> Vec<&str> {
+    let lines: Vec<_> = text
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .collect();
+    let end = lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty())
+        .map_or(0, |last| last + 1);
+    let lines = &lines[..end];
+    // Lines may be indented with different whitespace characters, so the shared
+    // indentation is the longest prefix their indentations have in common
+    let common = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .reduce(|common, indent| {
+            let len = common
+                .char_indices()
+                .zip(indent.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(indent.len()), |((i, _), _)| i);
+            &common[..len]
+        })
+        .unwrap_or("");
+    lines
+        .iter()
+        .map(|line| match line.trim().is_empty() {
+            true => "",
+            false => &line[common.len()..],
+        })
+        .collect()
+}